@@ -43,12 +43,13 @@ fn main() -> Result<(), std::io::Error> {
         let mut dirs = get_test_dirs(root_dir).expect("Unable to read tests directory");
         let mut result = vec!["".to_string()];
 
-        dirs.retain(|d| (d.is_dir()));
+        dirs.retain(|d| d.is_dir());
 
         for d in dirs.iter() {
             if let Ok(mut _dir) = d.read_dir() {
                 result.push(get_test_fn_template(
                     d.file_name().unwrap().to_str().unwrap(),
+                    std::fs::read_to_string(d.join("expected_panic.txt")).ok(),
                 ));
             }
         }
@@ -73,10 +74,20 @@ fn main() -> Result<(), std::io::Error> {
         tests_mod
     }
 
-    fn get_test_fn_template(dir_name: &str) -> String {
+    /// Tests for invalid input have the message they panic with in expected_panic.txt
+    fn get_test_fn_template(dir_name: &str, expected_panic: Option<String>) -> String {
         let test_name = dir_name.replace('-', "_");
         let mut test_fn_template = String::new();
         test_fn_template.push_str(include_str!("build_templates/test_fn.rs"));
+        if let Some(expected_panic) = expected_panic {
+            test_fn_template = test_fn_template.replace(
+                "#[test]",
+                &format!(
+                    "#[test]\n    #[should_panic(expected = {:?})]",
+                    expected_panic.trim()
+                ),
+            );
+        }
 
         test_fn_template = test_fn_template.replace("TEST_NAME", &test_name);
         test_fn_template = test_fn_template.replace("DIR_NAME", dir_name);
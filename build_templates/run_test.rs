@@ -4,6 +4,6 @@ extern crate scheduler;
 use scheduler::technical::input_output::Input;
 use scheduler::models::calendar;
 
-/// AUTO-GENERATED FILE. Do not change.
-/// Will be overwritten on build. Edit the file in build_templates or change test generation in build.rs
+// AUTO-GENERATED FILE. Do not change.
+// Will be overwritten on build. Edit the file in build_templates or change test generation in build.rs
 
@@ -31,39 +31,82 @@ mod TEST_MODULE_NAME {
         let actual_output_path = Path::new(&actual_output_path_str[..]);
 
         let input: Input = input_output::get_input_from_json(input_path).unwrap();
-        let desired_output: String =
-            input_output::get_output_string_from_json(output_path).unwrap();
-
-        // ONLY do this if expected is malformatted ... check that contents don't change!
-        // input_output::write_to_file(output_path, &desired_output).unwrap();
-
-        let mut calendar = Calendar::new(input.start_date, input.end_date);
 
+        let mut calendar = Calendar::new(
+            input.start_date,
+            input.end_date,
+            input.slot_minutes,
+            input.timezone,
+        );
+
+        calendar.add_priorities_from(&input.goals);
+        calendar.add_rounds_from(&input.goals);
+        calendar.add_busy_slots(&input.busy);
+        if let Some(previous) = &input.previous {
+            calendar.add_previous_schedule(previous);
+        }
+        if let Some(break_rule) = &input.breaks {
+            calendar.add_break_rule(break_rule, &input.goals);
+        }
         calendar.add_budgets_from(&input.goals);
-
-        //generate and place simple goal activities
-        let simple_goal_activities =
-            activity_generator::generate_simple_goal_activities(&calendar, &input.goals);
-        dbg!(&simple_goal_activities);
-        activity_placer::place(&mut calendar, simple_goal_activities);
-
-        //generate and place budget goal activities
-        let budget_goal_activities: Vec<Activity> =
-            activity_generator::generate_budget_goal_activities(&calendar, &input.goals);
-        dbg!(&calendar);
-        activity_placer::place(&mut calendar, budget_goal_activities);
-
-        calendar.log_impossible_min_day_budgets();
-
-        let get_to_week_min_budget_activities =
-            activity_generator::generate_get_to_week_min_budget_activities(&calendar, &input.goals);
-        activity_placer::place(&mut calendar, get_to_week_min_budget_activities);
-
-        calendar.log_impossible_min_week_budgets();
-
-        let top_up_week_budget_activities =
-            activity_generator::generate_top_up_week_budget_activities(&calendar, &input.goals);
-        activity_placer::place(&mut calendar, top_up_week_budget_activities);
+        calendar.add_pinned_tasks(&input.pinned, &input.goals);
+        calendar.log_impossible_children_durations(&input.goals);
+
+        //rounds go from the highest priority down, optional goals get their own rounds after the other goals
+        for rounds in calendar.get_round_groups() {
+            //the minimums of every round go before topping up, so extra time never beats a required minimum
+            for &round in &rounds {
+                //generate and place simple goal activities
+                let simple_goal_activities =
+                    activity_generator::generate_simple_goal_activities(
+                        &calendar,
+                        &input.goals,
+                        round,
+                    );
+                dbg!(&simple_goal_activities);
+                activity_placer::place(&mut calendar, simple_goal_activities);
+
+                //generate and place budget goal activities
+                let budget_goal_activities: Vec<Activity> =
+                    activity_generator::generate_budget_goal_activities(
+                        &calendar,
+                        &input.goals,
+                        round,
+                    );
+                dbg!(&calendar);
+                activity_placer::place(&mut calendar, budget_goal_activities);
+
+                calendar.log_impossible_min_day_budgets(round);
+
+                let get_to_week_min_budget_activities =
+                    activity_generator::generate_get_to_week_min_budget_activities(
+                        &calendar,
+                        &input.goals,
+                        round,
+                    );
+                activity_placer::place(&mut calendar, get_to_week_min_budget_activities);
+
+                calendar.log_impossible_min_week_budgets(round);
+            }
+
+            for &round in &rounds {
+                let top_up_week_budget_activities =
+                    activity_generator::generate_top_up_week_budget_activities(
+                        &calendar,
+                        &input.goals,
+                        round,
+                    );
+                activity_placer::place(&mut calendar, top_up_week_budget_activities);
+
+                let top_up_simple_goal_activities =
+                    activity_generator::generate_top_up_simple_goal_activities(
+                        &calendar,
+                        &input.goals,
+                        round,
+                    );
+                activity_placer::place(&mut calendar, top_up_simple_goal_activities);
+            }
+        }
 
         let output = calendar.print();
 
@@ -71,6 +114,13 @@ mod TEST_MODULE_NAME {
 
         input_output::write_to_file(actual_output_path, &actual_output).unwrap();
 
+        // read after scheduling, as tests for invalid input have no expected output
+        let desired_output: String =
+            input_output::get_output_string_from_json(output_path).unwrap();
+
+        // ONLY do this if expected is malformatted ... check that contents don't change!
+        // input_output::write_to_file(output_path, &desired_output).unwrap();
+
         (actual_output, desired_output)
     }
 }
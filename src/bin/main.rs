@@ -1,9 +1,7 @@
-use chrono::NaiveDateTime;
-use serde::Deserialize;
 use serde_json::{self, Value};
 use std::{fs, path::Path};
 extern crate scheduler;
-use scheduler::{models::goal::Goal, run_scheduler};
+use scheduler::{run_scheduler, technical::input_output::Input};
 fn main() {
     println!("Running!");
     let path = Path::new("./tests/jsons/stable/algorithm-challenge/input.json");
@@ -12,13 +10,5 @@ fn main() {
     dbg!(&json);
     let input: Input = serde_json::from_value(json).unwrap();
     dbg!(&input);
-    run_scheduler(input.start_date, input.end_date, input.goals);
-}
-
-#[derive(Deserialize, Debug)]
-#[serde(deny_unknown_fields, rename_all = "camelCase")]
-struct Input {
-    start_date: NaiveDateTime,
-    end_date: NaiveDateTime,
-    goals: Vec<Goal>,
+    run_scheduler(input);
 }
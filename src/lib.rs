@@ -52,8 +52,7 @@
 //! ZinZen&reg; trademark is a tool to protect the ZinZen&reg; identity and the
 //! quality perception of the ZinZen&reg; projects.
 
-use chrono::NaiveDateTime;
-use models::{activity::Activity, calendar::Calendar, goal::Goal, task::FinalTasks};
+use models::{activity::Activity, calendar::Calendar, task::FinalTasks};
 use serde_wasm_bindgen::{from_value, to_value};
 use services::activity_generator;
 use services::activity_placer;
@@ -69,7 +68,13 @@ const TS_APPEND_CONTENT: &'static str = r#"
 interface Input {
     startDate: string;
     endDate: string;
+    slotMinutes?: number;
+    timezone?: string;
     goals: number
+    busy?: { start: string; end: string; title: string }[];
+    pinned?: { goalId: string; start: string; duration: number | string }[];
+    previous?: object;
+    breaks?: { maxConsecutive: number | string; minBreak: number | string };
 }
 "#;
 
@@ -80,45 +85,76 @@ pub fn schedule(input: &JsValue) -> Result<JsValue, JsError> {
     console_error_panic_hook::set_once();
     // JsError implements From<Error>, so we can just use `?` on any Error
     let input: Input = from_value(input.clone()).unwrap();
-    let final_tasks = run_scheduler(input.start_date, input.end_date, input.goals);
+    let final_tasks = run_scheduler(input);
     Ok(to_value(&final_tasks)?)
 }
 
-pub fn run_scheduler(
-    start_date: NaiveDateTime,
-    end_date: NaiveDateTime,
-    goals: Vec<Goal>,
-) -> FinalTasks {
-    let mut calendar = Calendar::new(start_date, end_date);
+pub fn run_scheduler(input: Input) -> FinalTasks {
+    let goals = input.goals;
+    let mut calendar = Calendar::new(
+        input.start_date,
+        input.end_date,
+        input.slot_minutes,
+        input.timezone,
+    );
     dbg!(&calendar);
 
+    calendar.add_priorities_from(&goals);
+    calendar.add_rounds_from(&goals);
+    calendar.add_busy_slots(&input.busy);
+    if let Some(previous) = &input.previous {
+        calendar.add_previous_schedule(previous);
+    }
+    if let Some(break_rule) = &input.breaks {
+        calendar.add_break_rule(break_rule, &goals);
+    }
     calendar.add_budgets_from(&goals);
+    calendar.add_pinned_tasks(&input.pinned, &goals);
+    calendar.log_impossible_children_durations(&goals);
 
-    //generate and place simple goal activities
-    let simple_goal_activities =
-        activity_generator::generate_simple_goal_activities(&calendar, &goals);
-    dbg!(&simple_goal_activities);
-    activity_placer::place(&mut calendar, simple_goal_activities);
+    //rounds go from the highest priority down, optional goals get their own rounds after the other goals
+    for rounds in calendar.get_round_groups() {
+        //the minimums of every round go before topping up, so extra time never beats a required minimum
+        for &round in &rounds {
+            //generate and place simple goal activities
+            let simple_goal_activities =
+                activity_generator::generate_simple_goal_activities(&calendar, &goals, round);
+            dbg!(&simple_goal_activities);
+            activity_placer::place(&mut calendar, simple_goal_activities);
 
-    //generate and place budget goal activities
-    let budget_goal_activities: Vec<Activity> =
-        activity_generator::generate_budget_goal_activities(&calendar, &goals);
-    dbg!(&calendar);
-    activity_placer::place(&mut calendar, budget_goal_activities);
+            //generate and place budget goal activities
+            let budget_goal_activities: Vec<Activity> =
+                activity_generator::generate_budget_goal_activities(&calendar, &goals, round);
+            dbg!(&calendar);
+            activity_placer::place(&mut calendar, budget_goal_activities);
+
+            calendar.log_impossible_min_day_budgets(round);
 
-    calendar.log_impossible_min_day_budgets();
+            let get_to_week_min_budget_activities =
+                activity_generator::generate_get_to_week_min_budget_activities(
+                    &calendar, &goals, round,
+                );
+            activity_placer::place(&mut calendar, get_to_week_min_budget_activities);
+            //TODO: Test that day stays below min when week min being reached so other goals can get to the week min too
 
-    let get_to_week_min_budget_activities =
-        activity_generator::generate_get_to_week_min_budget_activities(&calendar, &goals);
-    activity_placer::place(&mut calendar, get_to_week_min_budget_activities);
-    //TODO: Test that day stays below min when week min being reached so other goals can get to the week min too
+            calendar.log_impossible_min_week_budgets(round);
+        }
 
-    calendar.log_impossible_min_week_budgets();
+        for &round in &rounds {
+            let top_up_week_budget_activities =
+                activity_generator::generate_top_up_week_budget_activities(
+                    &calendar, &goals, round,
+                );
+            activity_placer::place(&mut calendar, top_up_week_budget_activities);
+            //TODO: Test that day stays below min or max when week max being reachd
 
-    let top_up_week_budget_activities =
-        activity_generator::generate_top_up_week_budget_activities(&calendar, &goals);
-    activity_placer::place(&mut calendar, top_up_week_budget_activities);
-    //TODO: Test that day stays below min or max when week max being reachd
+            let top_up_simple_goal_activities =
+                activity_generator::generate_top_up_simple_goal_activities(
+                    &calendar, &goals, round,
+                );
+            activity_placer::place(&mut calendar, top_up_simple_goal_activities);
+        }
+    }
 
     calendar.print()
 }
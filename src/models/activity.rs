@@ -1,18 +1,21 @@
-use chrono::{Datelike, Days, Duration, NaiveDateTime};
+use chrono::{Datelike, Days, NaiveDateTime};
 use serde::Deserialize;
 
-use super::budget::Budget;
 use super::goal::Goal;
-use super::{calendar::Calendar, goal::Filters};
+use super::{
+    calendar::Calendar,
+    goal::{Filters, PreferredWindow},
+};
 use crate::models::budget::TimeBudget;
 use crate::models::calendar::Hour;
 use std::vec;
 use std::{
     fmt,
-    ops::{Add, Sub},
+    ops::Add,
     rc::{Rc, Weak},
 };
 
+/// All block sizes and durations are in calendar slots
 #[derive(Clone)]
 pub struct Activity {
     pub goal_id: String,
@@ -20,6 +23,21 @@ pub struct Activity {
     pub title: String,
     pub min_block_size: usize,
     pub max_block_size: usize,
+    /// Slots kept free before each placed block
+    pub buffer_before: usize,
+    /// Slots kept free after each placed block
+    pub buffer_after: usize,
+    /// Slots between blocks of the goal, unless they touch
+    pub min_gap: usize,
+    pub min_days_between: usize,
+    /// Times of day the activity is rather placed in
+    pub preferred_windows: Vec<PreferredWindow>,
+    pub priority: usize,
+    /// Skipped instead of impossible when it doesn't fit
+    pub optional: bool,
+    pub start: NaiveDateTime,
+    pub deadline: NaiveDateTime,
+    pub depends_on: Vec<String>,
     pub calendar_overlay: Vec<Option<Weak<Hour>>>,
     pub time_budgets: Vec<TimeBudget>,
     pub total_duration: usize,
@@ -29,6 +47,7 @@ pub struct Activity {
 impl Activity {
     pub fn get_compatible_hours_overlay(
         calendar: &Calendar,
+        goal_id: &str,
         filter_option: Option<Filters>,
         adjusted_goal_start: NaiveDateTime,
         adjusted_goal_deadline: NaiveDateTime,
@@ -38,30 +57,14 @@ impl Activity {
         for hour_index in 0..calendar.hours.capacity() {
             let mut compatible = true;
 
-            if filter_option.is_some() {
-                if filter_option.clone().unwrap().after_time
-                    < filter_option.clone().unwrap().before_time
-                {
-                    //normal case
-                    let hour_of_day = hour_index % 24;
-                    if hour_of_day < filter_option.clone().unwrap().after_time {
-                        compatible = false;
-                    }
-                    if hour_of_day >= filter_option.clone().unwrap().before_time {
-                        compatible = false;
-                    }
+            if let Some(filter) = &filter_option {
+                let minute_of_day = calendar.get_minute_of_day(hour_index);
+                if filter.windows_contain(calendar.get_week_day_of(hour_index), minute_of_day) {
+                    // OK
                 } else {
-                    // special case where we know that compatible times cross the midnight boundary
-                    let hour_of_day = hour_index % 24;
-                    if hour_of_day >= filter_option.clone().unwrap().before_time
-                        && hour_of_day < filter_option.clone().unwrap().after_time
-                    {
-                        compatible = false;
-                    }
+                    compatible = false;
                 }
-                if filter_option
-                    .as_ref()
-                    .unwrap()
+                if filter
                     .on_days
                     .contains(&calendar.get_week_day_of(hour_index))
                 {
@@ -69,6 +72,15 @@ impl Activity {
                 } else {
                     compatible = false;
                 }
+                let date_time_of_hour = calendar.get_date_time_of(hour_index);
+                let date_time_after_hour = calendar.get_date_time_of(hour_index + 1);
+                if filter
+                    .not_on
+                    .iter()
+                    .any(|slot| slot.overlaps(date_time_of_hour, date_time_after_hour))
+                {
+                    compatible = false;
+                }
             }
 
             if hour_index < calendar.get_index_of(adjusted_goal_start) {
@@ -81,11 +93,17 @@ impl Activity {
             //check if hour is already occupied by some other activity (for later rounds of scheduling partly occupied calendar)
             match &*calendar.hours[hour_index] {
                 Hour::Free => {}
+                // a goal can extend its blocks into its own buffers
+                Hour::Buffer {
+                    activity_goalid, ..
+                } if activity_goalid == goal_id => {}
                 Hour::Occupied {
                     activity_index: _,
                     activity_title: _,
-                    activity_goalid: _activity_goalid,
-                } => {
+                    activity_goalid: _,
+                }
+                | Hour::Blocked { .. }
+                | Hour::Buffer { .. } => {
                     compatible = false;
                 }
             }
@@ -123,8 +141,38 @@ impl Activity {
         flex
     }
 
-    pub fn get_best_scheduling_index_and_length(&self) -> Option<(usize, usize)> {
-        let mut best_scheduling_index_and_conflicts: Option<(usize, usize, usize)> = None;
+    /// Prefers the positions the goal had in the previous schedule, as far as they are still possible
+    pub fn get_best_scheduling_index_and_length(
+        &self,
+        calendar: &Calendar,
+    ) -> Option<(usize, usize)> {
+        match self.activity_type {
+            ActivityType::SimpleGoal => {
+                // try the session size first, and smaller sessions if that doesn't fit anywhere
+                let mut size = self.get_session_size();
+                loop {
+                    let best_index_and_length = self.get_best_scheduling_index_for(size, calendar);
+                    if best_index_and_length.is_some() || size <= self.min_block_size {
+                        return best_index_and_length;
+                    }
+                    size -= 1;
+                }
+            }
+            ActivityType::Budget
+            | ActivityType::GetToMinWeekBudget
+            | ActivityType::TopUpWeekBudget
+            | ActivityType::TopUpSimpleGoal => {
+                self.get_best_scheduling_index_for(self.get_block_size(), calendar)
+            }
+        }
+    }
+
+    fn get_best_scheduling_index_for(
+        &self,
+        offset_size: usize,
+        calendar: &Calendar,
+    ) -> Option<(usize, usize)> {
+        let mut best_scheduling_index_and_score: Option<(usize, isize, usize, usize)> = None;
         for hour_index in 0..self.calendar_overlay.len() {
             let mut conflicts = 0;
             match &self.calendar_overlay[hour_index] {
@@ -132,41 +180,49 @@ impl Activity {
                     continue;
                 }
                 Some(_) => {
-                    //TODO: shouldn't this logic be in creating the activity and then set to min_block_size so we can just use that here?
-                    let offset_size: usize = match self.activity_type {
-                        ActivityType::SimpleGoal => self.total_duration,
-                        ActivityType::Budget => self.min_block_size,
-                        ActivityType::GetToMinWeekBudget => 1,
-                        ActivityType::TopUpWeekBudget => 1,
-                    };
+                    if !self.has_room_for_buffers(hour_index, offset_size, calendar)
+                        || calendar.get_block_size_of(&self.goal_id, hour_index, offset_size)
+                            > self.max_block_size
+                        || !calendar.is_within_break_rule(&self.goal_id, hour_index, offset_size)
+                    {
+                        continue;
+                    }
                     for offset in 0..offset_size {
-                        match &self.calendar_overlay[hour_index + offset] {
-                            None => {
-                                // panic!("Does this ever happen?");
-                                //      Yes in algorithm_challenge test case
-                                //      TODO: do we need to mark all from hour_index till offset as None?"
-                                continue;
-                            }
-                            Some(weak) => {
-                                if weak.upgrade().is_none() {
-                                    break; // this will reset conflicts too
+                        // the block only fits if every slot of it is still available
+                        let Some(Some(weak)) = self.calendar_overlay.get(hour_index + offset)
+                        else {
+                            break;
+                        };
+                        if weak.upgrade().is_none() {
+                            break;
+                        }
+                        conflicts += weak.weak_count();
+                        //if last position check if best so far - or so little we can break
+                        if offset == offset_size - 1 {
+                            let slots_there_before =
+                                self.get_slots_there_before(calendar, hour_index, offset_size);
+                            // preferred windows weigh against the claims of other activities
+                            let score = self.get_preference_for(calendar, hour_index, offset_size)
+                                as isize
+                                - conflicts as isize;
+                            match best_scheduling_index_and_score {
+                                None => {
+                                    best_scheduling_index_and_score =
+                                        Some((hour_index, score, offset_size, slots_there_before));
                                 }
-                                conflicts += weak.weak_count();
-                                //if last position check if best so far - or so little we can break
-                                if offset == offset_size - 1 {
-                                    match best_scheduling_index_and_conflicts {
-                                        None => {
-                                            best_scheduling_index_and_conflicts =
-                                                Some((hour_index, conflicts, offset_size));
-                                        }
-                                        Some((_, best_conflicts, _)) => {
-                                            if conflicts < best_conflicts || conflicts == 0 {
-                                                best_scheduling_index_and_conflicts =
-                                                    Some((hour_index, conflicts, offset_size));
-                                            }
-                                        }
+                                Some((_, best_score, _, best_slots_there_before)) => {
+                                    if slots_there_before > best_slots_there_before
+                                        || (slots_there_before == best_slots_there_before
+                                            && (score > best_score
+                                                || (score == best_score && conflicts == 0)))
+                                    {
+                                        best_scheduling_index_and_score = Some((
+                                            hour_index,
+                                            score,
+                                            offset_size,
+                                            slots_there_before,
+                                        ));
                                     }
-                                    continue;
                                 }
                             }
                         }
@@ -174,7 +230,72 @@ impl Activity {
                 }
             }
         }
-        best_scheduling_index_and_conflicts.map(|(best_index, _, size)| (best_index, size))
+        best_scheduling_index_and_score.map(|(best_index, _, size, _)| (best_index, size))
+    }
+
+    fn has_room_for_buffers(&self, index: usize, size: usize, calendar: &Calendar) -> bool {
+        let before = index.saturating_sub(self.buffer_before)..index;
+        let after = index + size..(index + size + self.buffer_after).min(calendar.hours.len());
+        before
+            .chain(after)
+            .all(|index| calendar.can_hold_buffer_of(&self.goal_id, index))
+    }
+
+    /// Buffers of the own goal replace free hours, so they are pointed to again
+    pub(crate) fn claim_own_buffers(&mut self, calendar: &Calendar, buffer_indexes: &[usize]) {
+        for index in buffer_indexes {
+            if self.calendar_overlay[*index].is_some() {
+                self.calendar_overlay[*index] = Some(Rc::downgrade(&calendar.hours[*index]));
+            }
+        }
+    }
+
+    /// Sum of the weights of the preferred windows over the slots of the block
+    fn get_preference_for(&self, calendar: &Calendar, index: usize, size: usize) -> usize {
+        (index..index + size)
+            .map(|index| {
+                let minute_of_day = calendar.get_minute_of_day(index);
+                self.preferred_windows
+                    .iter()
+                    .filter(|preferred_window| preferred_window.window.contains(minute_of_day))
+                    .map(|preferred_window| preferred_window.weight)
+                    .sum::<usize>()
+            })
+            .sum()
+    }
+
+    /// Number of slots in the block that the goal had in the previous schedule
+    fn get_slots_there_before(&self, calendar: &Calendar, index: usize, size: usize) -> usize {
+        (index..index + size)
+            .filter(|index| calendar.previous_goal_ids[*index].as_ref() == Some(&self.goal_id))
+            .count()
+    }
+
+    /// Sessions are spread evenly over the duration left, without going over max_block_size.
+    /// For example 20 hours with a max of 8 hours is placed as 7, 7 and 6 hours.
+    fn get_session_size(&self) -> usize {
+        let sessions = self.duration_left.div_ceil(self.max_block_size);
+        self.duration_left.div_ceil(sessions)
+    }
+
+    /// Blocks are placed with min_block_size, unless what is left after the block would be too small for another block.
+    /// Then the rest is placed in one go, as far as max_block_size allows.
+    fn get_block_size(&self) -> usize {
+        if self.duration_left < self.min_block_size * 2 {
+            self.duration_left.min(self.max_block_size)
+        } else {
+            self.min_block_size
+        }
+    }
+
+    /// Removes the hours right before and after a placed session, so the next session doesn't make it longer than max_block_size
+    pub(crate) fn keep_sessions_apart(&mut self, session_index: usize, session_size: usize) {
+        if session_index > 0 {
+            self.calendar_overlay[session_index - 1] = None;
+        }
+        if session_index + session_size < self.calendar_overlay.len() {
+            self.calendar_overlay[session_index + session_size] = None;
+        }
     }
 
     pub(crate) fn release_claims(&mut self) {
@@ -190,39 +311,39 @@ impl Activity {
         goal: &Goal,
         calendar: &Calendar,
     ) -> Vec<Activity> {
-        if goal.children.is_some() || goal.filters.as_ref().is_none() {
-            return vec![];
-        }
-        if goal.budget_config.as_ref().unwrap().min_per_day == 0 {
+        if goal.children.is_some() || goal.budget_config.is_none() {
             return vec![];
         }
         let (adjusted_goal_start, adjusted_goal_deadline) = goal.get_adj_start_deadline(calendar);
         let mut activities: Vec<Activity> = Vec::with_capacity(1);
-        let filter_option = goal.filters.clone().unwrap();
+        let (buffer_before, buffer_after) = goal.get_buffers(calendar);
+        let filter_option = goal.filters.clone().unwrap_or_default();
 
-        //TODO: This is cutting something like Sleep into pieces
-        //Replace by an if on title == 'sleep' / "Sleep" / "Sleep 😴🌙"?
-        //Yes ... but what about translations? => better to match on goalid
-        let mut adjusted_min_block_size = 1;
-        if goal.title.contains("leep") {
-            adjusted_min_block_size = goal.budget_config.as_ref().unwrap().min_per_day;
-        }
+        let budget_config = goal.budget_config.as_ref().unwrap();
 
         for day in 0..(adjusted_goal_deadline - adjusted_goal_start).num_days() as u64 {
-            if filter_option
-                .on_days
-                .contains(&adjusted_goal_start.add(Days::new(day)).weekday())
-            {
+            let weekday = adjusted_goal_start.add(Days::new(day)).weekday();
+            if filter_option.on_days.contains(&weekday) {
                 // OK
             } else {
                 // This day is not allowed
                 continue;
             }
+            let min_per_day = budget_config.get_min_per_day_on(weekday);
+            if min_per_day == 0 {
+                continue;
+            }
+            let (min_block_size, max_block_size) = goal.get_block_sizes(
+                calendar,
+                1,
+                calendar.get_slots_for_minutes(budget_config.get_max_per_day_on(weekday)),
+            );
             let activity_start = adjusted_goal_start.add(Days::new(day));
             let activity_deadline = adjusted_goal_start.add(Days::new(day + 1));
 
             let compatible_hours_overlay = Activity::get_compatible_hours_overlay(
                 calendar,
+                &goal.id,
                 Some(filter_option.clone()),
                 activity_start,
                 activity_deadline,
@@ -232,12 +353,22 @@ impl Activity {
                 goal_id: goal.id.clone(),
                 activity_type: ActivityType::Budget,
                 title: goal.title.clone(),
-                min_block_size: adjusted_min_block_size,
-                max_block_size: goal.budget_config.as_ref().unwrap().max_per_day,
+                min_block_size,
+                max_block_size,
+                buffer_before,
+                buffer_after,
+                min_gap: calendar.get_slots_for_minutes(goal.min_gap.unwrap_or(0)),
+                min_days_between: goal.min_days_between.unwrap_or(0),
+                preferred_windows: goal.preferred_windows.clone(),
+                priority: calendar.get_priority_of(&goal.id),
+                optional: goal.optional,
+                start: activity_start,
+                deadline: activity_deadline,
+                depends_on: goal.depends_on.clone().unwrap_or_default(),
                 calendar_overlay: compatible_hours_overlay,
                 time_budgets: vec![],
-                total_duration: adjusted_min_block_size,
-                duration_left: goal.budget_config.as_ref().unwrap().min_per_day,
+                total_duration: min_block_size,
+                duration_left: calendar.get_slots_for_minutes(min_per_day),
                 status: Status::Unprocessed,
             };
             dbg!(&activity);
@@ -250,46 +381,235 @@ impl Activity {
         goal: &Goal,
         calendar: &Calendar,
     ) -> Vec<Activity> {
-        if goal.children.is_some() || goal.filters.as_ref().is_some() {
+        if goal.children.is_some() || goal.budget_config.is_some() {
             return vec![];
         }
-        let (adjusted_goal_start, adjusted_goal_deadline) = goal.get_adj_start_deadline(calendar);
-        let mut activities: Vec<Activity> = Vec::with_capacity(1);
+        let occurrences = Activity::get_occurrences_of(goal, calendar);
+        let mut activities: Vec<Activity> = Vec::with_capacity(occurrences.len());
+        let (buffer_before, buffer_after) = goal.get_buffers(calendar);
+
+        let activity_total_duration = calendar.get_slots_for_minutes(goal.min_duration.unwrap());
+        // time already spent is taken off the first occurrences
+        let mut completed_left = calendar.get_slots_for_minutes(goal.completed.unwrap_or(0));
+
+        for (occurrence_start, occurrence_deadline) in occurrences {
+            let completed = completed_left.min(activity_total_duration);
+            completed_left -= completed;
+            let pinned = calendar.get_slots_of_goal_between(
+                &goal.id,
+                calendar.get_index_of(occurrence_start),
+                calendar.get_index_of(occurrence_deadline),
+            );
+            let duration_left = activity_total_duration.saturating_sub(completed + pinned);
+            if duration_left == 0 {
+                continue;
+            }
+            let (min_block_size, max_block_size) =
+                Activity::get_simple_goal_block_sizes(goal, calendar, duration_left);
+            let compatible_hours_overlay = Activity::get_compatible_hours_overlay(
+                calendar,
+                &goal.id,
+                goal.filters.clone(),
+                occurrence_start,
+                occurrence_deadline,
+            );
 
-        let activity_total_duration = goal.min_duration.unwrap();
-        let mut min_block_size = activity_total_duration;
-        if activity_total_duration > 8 {
-            min_block_size = 1;
-            //todo!() //split into multiple activities so flexibilities are correct??
-            // or yield flex 1 or maximum of the set from activity.flex()?
-        };
+            let activity = Activity {
+                goal_id: goal.id.clone(),
+                activity_type: ActivityType::SimpleGoal,
+                title: goal.title.clone(),
+                min_block_size,
+                max_block_size,
+                buffer_before,
+                buffer_after,
+                min_gap: calendar.get_slots_for_minutes(goal.min_gap.unwrap_or(0)),
+                min_days_between: goal.min_days_between.unwrap_or(0),
+                preferred_windows: goal.preferred_windows.clone(),
+                priority: calendar.get_priority_of(&goal.id),
+                optional: goal.optional,
+                start: occurrence_start,
+                deadline: occurrence_deadline,
+                depends_on: goal.depends_on.clone().unwrap_or_default(),
+                calendar_overlay: compatible_hours_overlay,
+                time_budgets: vec![],
+                total_duration: activity_total_duration,
+                duration_left,
+                status: Status::Unprocessed,
+            };
+            dbg!(&activity);
+            activities.push(activity);
+        }
 
-        let compatible_hours_overlay = Activity::get_compatible_hours_overlay(
-            calendar,
-            goal.filters.clone(),
-            adjusted_goal_start,
-            adjusted_goal_deadline,
-        );
+        activities
+    }
 
-        let activity = Activity {
-            goal_id: goal.id.clone(),
-            activity_type: ActivityType::SimpleGoal,
-            title: goal.title.clone(),
-            min_block_size,
-            max_block_size: min_block_size,
-            calendar_overlay: compatible_hours_overlay,
-            time_budgets: vec![],
-            total_duration: activity_total_duration,
-            duration_left: min_block_size, //TODO: Correct this - is it even necessary to have duration_left?
-            status: Status::Unprocessed,
+    /// Activities for the hours between min_duration and max_duration, in the time left after the other passes
+    pub(crate) fn get_activities_to_top_up_simple_goal(
+        goal: &Goal,
+        calendar: &Calendar,
+    ) -> Vec<Activity> {
+        if goal.children.is_some() || goal.budget_config.is_some() {
+            return vec![];
+        }
+        let Some(max_duration) = goal.max_duration else {
+            return vec![];
         };
-        dbg!(&activity);
-        activities.push(activity);
+        if max_duration < goal.min_duration.unwrap_or(0) {
+            panic!(
+                "Max duration {:?} below min duration {:?} for goal {:?}",
+                goal.max_duration, goal.min_duration, goal.title
+            );
+        }
+        let occurrences = Activity::get_occurrences_of(goal, calendar);
+        let mut activities: Vec<Activity> = Vec::with_capacity(occurrences.len());
+        let (buffer_before, buffer_after) = goal.get_buffers(calendar);
+
+        let activity_total_duration = calendar.get_slots_for_minutes(max_duration);
+        let mut completed_left = calendar.get_slots_for_minutes(goal.completed.unwrap_or(0));
+
+        for (occurrence_start, occurrence_deadline) in occurrences {
+            let completed = completed_left.min(activity_total_duration);
+            completed_left -= completed;
+            let scheduled = calendar.get_slots_of_goal_between(
+                &goal.id,
+                calendar.get_index_of(occurrence_start),
+                calendar.get_index_of(occurrence_deadline),
+            );
+            let duration_left = activity_total_duration.saturating_sub(completed + scheduled);
+            if duration_left == 0 {
+                continue;
+            }
+            // these extend the blocks of the earlier passes, so only the max block size applies
+            let max_block_size = match goal.max_block_size {
+                Some(minutes) => calendar.get_slots_for_minutes(minutes),
+                None => calendar.hours.len(),
+            };
+            let compatible_hours_overlay = Activity::get_compatible_hours_overlay(
+                calendar,
+                &goal.id,
+                goal.filters.clone(),
+                occurrence_start,
+                occurrence_deadline,
+            );
 
+            activities.push(Activity {
+                goal_id: goal.id.clone(),
+                activity_type: ActivityType::TopUpSimpleGoal,
+                title: goal.title.clone(),
+                min_block_size: 1,
+                max_block_size,
+                buffer_before,
+                buffer_after,
+                min_gap: calendar.get_slots_for_minutes(goal.min_gap.unwrap_or(0)),
+                min_days_between: goal.min_days_between.unwrap_or(0),
+                preferred_windows: goal.preferred_windows.clone(),
+                priority: calendar.get_priority_of(&goal.id),
+                optional: goal.optional,
+                start: occurrence_start,
+                deadline: occurrence_deadline,
+                depends_on: goal.depends_on.clone().unwrap_or_default(),
+                calendar_overlay: compatible_hours_overlay,
+                time_budgets: vec![],
+                total_duration: activity_total_duration,
+                duration_left,
+                status: Status::Unprocessed,
+            });
+        }
         activities
     }
 
-    pub fn update_overlay_with(&mut self, budgets: &Vec<Budget>) {
+    fn get_occurrences_of(goal: &Goal, calendar: &Calendar) -> Vec<(NaiveDateTime, NaiveDateTime)> {
+        let (adjusted_goal_start, adjusted_goal_deadline) = goal.get_adj_start_deadline(calendar);
+        match &goal.repeat {
+            Some(repetition) => repetition.get_occurrences(
+                adjusted_goal_start,
+                adjusted_goal_deadline,
+                goal.get_start(calendar).date(),
+            ),
+            None => vec![(adjusted_goal_start, adjusted_goal_deadline)],
+        }
+    }
+
+    fn get_simple_goal_block_sizes(
+        goal: &Goal,
+        calendar: &Calendar,
+        duration: usize,
+    ) -> (usize, usize) {
+        // up to 8 hours, or what the break rule allows, is done in one go
+        // longer goals or goals with a max block size are split into sessions
+        let max_session_size = match calendar.get_max_consecutive_slots_for(&goal.id) {
+            Some(max_consecutive_slots) => {
+                max_consecutive_slots.min(calendar.get_slots_for_hours(8))
+            }
+            None => calendar.get_slots_for_hours(8),
+        };
+        if duration > max_session_size || goal.max_block_size.is_some() {
+            let default_min_block_size = calendar.get_slots_for_hours(1).min(duration);
+            let default_max_block_size = match goal.min_block_size {
+                Some(minutes) => max_session_size.max(calendar.get_slots_for_minutes(minutes)),
+                None => max_session_size,
+            };
+            goal.get_block_sizes(calendar, default_min_block_size, default_max_block_size)
+        } else {
+            // what is left after completed time can be shorter than min_block_size
+            let default_max_block_size = match goal.min_block_size {
+                Some(minutes) => duration.max(calendar.get_slots_for_minutes(minutes)),
+                None => duration,
+            };
+            goal.get_block_sizes(calendar, duration, default_max_block_size)
+        }
+    }
+
+    /// Removes all hours up to the end of the last hour scheduled for the goals this activity depends on.
+    /// If one of those goals hasn't been scheduled before the deadline of this activity, it can't start at all.
+    pub fn update_overlay_with_dependencies(&mut self, calendar: &Calendar) {
+        if self.depends_on.is_empty()
+            || self.status == Status::Scheduled
+            || self.status == Status::Impossible
+            || self.status == Status::Processed
+        {
+            return;
+        }
+        let deadline_index = calendar.get_index_of(self.deadline);
+        let mut earliest_start_index = 0;
+        for goal_id in &self.depends_on {
+            match calendar.get_end_index_of_goal_before(goal_id, deadline_index) {
+                Some(end_index) => {
+                    earliest_start_index = earliest_start_index.max(end_index);
+                }
+                None => {
+                    self.release_claims();
+                    self.status = Status::Impossible;
+                    return;
+                }
+            }
+        }
+        for hour_index in 0..earliest_start_index.min(self.calendar_overlay.len()) {
+            self.calendar_overlay[hour_index] = None;
+        }
+    }
+
+    /// Removes all hours from the first hour scheduled for the goals depending on this activity,
+    /// so topping up a goal doesn't add time after a goal waiting for it already started.
+    pub fn update_overlay_with_dependents(&mut self, calendar: &Calendar, goals: &[Goal]) {
+        let Some(dependent_start_index) = goals
+            .iter()
+            .filter(|goal| {
+                goal.depends_on
+                    .as_ref()
+                    .is_some_and(|goal_ids| goal_ids.contains(&self.goal_id))
+            })
+            .filter_map(|goal| calendar.get_start_index_of_goal(&goal.id))
+            .min()
+        else {
+            return;
+        };
+        for hour_index in dependent_start_index..self.calendar_overlay.len() {
+            self.calendar_overlay[hour_index] = None;
+        }
+    }
+
+    pub fn update_overlay_with(&mut self, calendar: &Calendar) {
         if self.status == Status::Scheduled
             || self.status == Status::Impossible
             || self.status == Status::Processed
@@ -312,6 +632,53 @@ impl Activity {
             }
         }
 
+        //Check if hour keeps its distance to the blocks already placed for the goal
+        if self.min_gap > 0 || self.min_days_between > 0 {
+            let days_of_goal = calendar.get_days_of_goal(&self.goal_id);
+            for hour_index in 0..self.calendar_overlay.len() {
+                if self.calendar_overlay[hour_index].is_none() {
+                    continue;
+                }
+                let day = calendar.get_date_time_of(hour_index).date();
+                if !calendar.keeps_min_gap_of(&self.goal_id, hour_index, self.min_gap)
+                    || days_of_goal.iter().any(|day_of_goal| {
+                        *day_of_goal != day
+                            && (day - *day_of_goal).num_days().unsigned_abs() as usize
+                                <= self.min_days_between
+                    })
+                {
+                    self.calendar_overlay[hour_index] = None;
+                }
+            }
+        }
+
+        //Check if hour is in at least one block that keeps a break when needed
+        if calendar
+            .get_max_consecutive_slots_for(&self.goal_id)
+            .is_some()
+        {
+            let mut is_part_of_block_within_break_rule = vec![false; self.calendar_overlay.len()];
+            for index in 0..self
+                .calendar_overlay
+                .len()
+                .saturating_sub(self.min_block_size)
+            {
+                if (index..index + self.min_block_size)
+                    .all(|index| self.calendar_overlay[index].is_some())
+                    && calendar.is_within_break_rule(&self.goal_id, index, self.min_block_size)
+                {
+                    for offset in 0..self.min_block_size {
+                        is_part_of_block_within_break_rule[index + offset] = true;
+                    }
+                }
+            }
+            for (index, is_part_of_block) in is_part_of_block_within_break_rule.iter().enumerate() {
+                if !*is_part_of_block {
+                    self.calendar_overlay[index] = None;
+                }
+            }
+        }
+
         //Check if blocks are too small
         let mut block_size_found: usize = 0;
         for hour_index in 0..self.calendar_overlay.len() {
@@ -349,7 +716,7 @@ impl Activity {
         let mut is_part_of_at_least_one_valid_block_placing_option: Vec<bool> =
             vec![false; self.calendar_overlay.len()];
         let mut is_activity_part_of_budget = false;
-        for budget in budgets {
+        for budget in &calendar.budgets {
             //check if activity goal id is in the budget - else don't bother
             if budget.participating_goals.contains(&self.goal_id) {
                 // great, process it
@@ -397,27 +764,40 @@ impl Activity {
     ) -> Vec<Activity> {
         let mut activities: Vec<Activity> = vec![];
 
+        let activity_start = calendar.get_date_time_of(time_budget.calendar_start_index);
+        let activity_deadline = calendar.get_date_time_of(time_budget.calendar_end_index);
         let compatible_hours_overlay = Activity::get_compatible_hours_overlay(
             calendar,
+            &goal_to_use.id,
             goal_to_use.filters.clone(),
-            calendar
-                .start_date_time
-                .sub(Duration::hours(24)) //TODO: fix magic number
-                .add(Duration::hours(time_budget.calendar_start_index as i64)),
-            calendar
-                .start_date_time
-                .sub(Duration::hours(24)) //TODO: fix magic number
-                .add(Duration::hours(time_budget.calendar_end_index as i64)),
+            activity_start,
+            activity_deadline,
         );
 
         let max_hours = time_budget.max_scheduled - time_budget.scheduled;
+        let (buffer_before, buffer_after) = goal_to_use.get_buffers(calendar);
+        // these extend the blocks of the earlier passes, so only the max block size applies
+        let max_block_size = match goal_to_use.max_block_size {
+            Some(minutes) => calendar.get_slots_for_minutes(minutes),
+            None => calendar.hours.len(),
+        };
 
         activities.push(Activity {
             goal_id: goal_to_use.id.clone(),
             activity_type: ActivityType::GetToMinWeekBudget,
             title: goal_to_use.title.clone(),
             min_block_size: 1,
-            max_block_size: max_hours,
+            max_block_size,
+            buffer_before,
+            buffer_after,
+            min_gap: calendar.get_slots_for_minutes(goal_to_use.min_gap.unwrap_or(0)),
+            min_days_between: goal_to_use.min_days_between.unwrap_or(0),
+            preferred_windows: goal_to_use.preferred_windows.clone(),
+            priority: calendar.get_priority_of(&goal_to_use.id),
+            optional: goal_to_use.optional,
+            start: activity_start,
+            deadline: activity_deadline,
+            depends_on: goal_to_use.depends_on.clone().unwrap_or_default(),
             calendar_overlay: compatible_hours_overlay,
             time_budgets: vec![],
             total_duration: max_hours,
@@ -435,27 +815,40 @@ impl Activity {
     ) -> Vec<Activity> {
         let mut activities: Vec<Activity> = vec![];
 
+        let activity_start = calendar.get_date_time_of(time_budget.calendar_start_index);
+        let activity_deadline = calendar.get_date_time_of(time_budget.calendar_end_index);
         let compatible_hours_overlay = Activity::get_compatible_hours_overlay(
             calendar,
+            &goal_to_use.id,
             goal_to_use.filters.clone(),
-            calendar
-                .start_date_time
-                .sub(Duration::hours(24)) //TODO: fix magic number
-                .add(Duration::hours(time_budget.calendar_start_index as i64)),
-            calendar
-                .start_date_time
-                .sub(Duration::hours(24)) //TODO: fix magic number
-                .add(Duration::hours(time_budget.calendar_end_index as i64)),
+            activity_start,
+            activity_deadline,
         );
 
         let max_hours = time_budget.max_scheduled - time_budget.scheduled;
+        let (buffer_before, buffer_after) = goal_to_use.get_buffers(calendar);
+        // these extend the blocks of the earlier passes, so only the max block size applies
+        let max_block_size = match goal_to_use.max_block_size {
+            Some(minutes) => calendar.get_slots_for_minutes(minutes),
+            None => calendar.hours.len(),
+        };
 
         activities.push(Activity {
             goal_id: goal_to_use.id.clone(),
             activity_type: ActivityType::TopUpWeekBudget,
             title: goal_to_use.title.clone(),
             min_block_size: 1,
-            max_block_size: max_hours,
+            max_block_size,
+            buffer_before,
+            buffer_after,
+            min_gap: calendar.get_slots_for_minutes(goal_to_use.min_gap.unwrap_or(0)),
+            min_days_between: goal_to_use.min_days_between.unwrap_or(0),
+            preferred_windows: goal_to_use.preferred_windows.clone(),
+            priority: calendar.get_priority_of(&goal_to_use.id),
+            optional: goal_to_use.optional,
+            start: activity_start,
+            deadline: activity_deadline,
+            depends_on: goal_to_use.depends_on.clone().unwrap_or_default(),
             calendar_overlay: compatible_hours_overlay,
             time_budgets: vec![],
             total_duration: max_hours,
@@ -481,6 +874,8 @@ pub enum ActivityType {
     Budget,
     GetToMinWeekBudget,
     TopUpWeekBudget,
+    /// The hours between min_duration and max_duration of a simple goal
+    TopUpSimpleGoal,
 }
 
 impl fmt::Debug for Activity {
@@ -492,8 +887,6 @@ impl fmt::Debug for Activity {
         writeln!(f, "duration left: {:?}", self.duration_left).unwrap();
         writeln!(f, "flex:{:?}", self.flex()).unwrap();
         for hour_index in 0..self.calendar_overlay.capacity() {
-            let day_index = hour_index / 24;
-            let hour_of_day = hour_index % 24;
             match &self.calendar_overlay[hour_index] {
                 None => {
                     write!(f, "-").unwrap();
@@ -501,9 +894,7 @@ impl fmt::Debug for Activity {
                 Some(weak) => {
                     writeln!(
                         f,
-                        "day {:?} - hour {:?} at index {:?}: {:?} claims but {:?}",
-                        day_index,
-                        hour_of_day,
+                        "index {:?}: {:?} claims but {:?}",
                         hour_index,
                         weak.weak_count(),
                         weak.upgrade().unwrap()
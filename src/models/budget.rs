@@ -1,9 +1,9 @@
 use std::{
     fmt::{Debug, Formatter},
-    ops::{Add, Sub},
+    ops::Add,
 };
 
-use chrono::{Datelike, Duration};
+use chrono::{Datelike, Duration, Months, NaiveDateTime};
 use serde::Deserialize;
 
 use super::{activity::ActivityType, calendar::Calendar, goal::Goal};
@@ -12,19 +12,81 @@ use super::{activity::ActivityType, calendar::Calendar, goal::Goal};
 pub struct Budget {
     pub originating_goal_id: String,
     pub participating_goals: Vec<String>,
+    /// Budget of an optional goal, its shortfalls are skipped instead of impossible
+    pub optional: bool,
     pub time_budgets: Vec<TimeBudget>,
+    pub days_budgets: Vec<DaysBudget>,
 }
 impl Budget {
     pub fn reduce_for_(&mut self, goal: &str, duration_offset: usize) {
         if self.participating_goals.contains(&goal.to_string()) {
-            let iterator = self.time_budgets.iter_mut().enumerate();
-            for (_, time_budget) in iterator {
+            let iterator = self.time_budgets.iter_mut();
+            for time_budget in iterator {
                 if duration_offset >= time_budget.calendar_start_index
                     && duration_offset < time_budget.calendar_end_index
                 {
                     time_budget.scheduled += 1
                 }
             }
+            if let Some(day_start_index) = self.get_day_start_index_of(duration_offset) {
+                for days_budget in &mut self.days_budgets {
+                    if days_budget.contains(duration_offset)
+                        && !days_budget.days_scheduled.contains(&day_start_index)
+                    {
+                        days_budget.days_scheduled.push(day_start_index);
+                    }
+                }
+            }
+        }
+    }
+
+    fn get_day_start_index_of(&self, hour_index: usize) -> Option<usize> {
+        self.time_budgets
+            .iter()
+            .find(|time_budget| {
+                time_budget.time_budget_type == TimeBudgetType::Day
+                    && hour_index >= time_budget.calendar_start_index
+                    && hour_index < time_budget.calendar_end_index
+            })
+            .map(|time_budget| time_budget.calendar_start_index)
+    }
+
+    /// Days without the goal don't need to get to the min of the day when the days per week are budgeted
+    pub fn is_day_off(&self, time_budget: &TimeBudget) -> bool {
+        time_budget.time_budget_type == TimeBudgetType::Day
+            && time_budget.scheduled == 0
+            && self
+                .days_budgets
+                .iter()
+                .any(|days_budget| days_budget.contains(time_budget.calendar_start_index))
+    }
+
+    /// Each missing day needs at least the smallest min of the days in the week
+    pub fn get_min_slots_of_missing_days(&self, days_budget: &DaysBudget) -> usize {
+        let smallest_min_per_day = self
+            .time_budgets
+            .iter()
+            .filter(|time_budget| {
+                time_budget.time_budget_type == TimeBudgetType::Day
+                    && days_budget.contains(time_budget.calendar_start_index)
+                    && time_budget.min_scheduled > 0
+            })
+            .map(|time_budget| time_budget.min_scheduled)
+            .min()
+            .unwrap_or(0);
+        smallest_min_per_day * days_budget.get_days_missing()
+    }
+
+    /// Time already spent counts for the week, month and custom period running at the start of the calendar
+    pub fn add_completed_for(&mut self, goal: &str, slots: usize, calendar_start_index: usize) {
+        if self.participating_goals.contains(&goal.to_string()) {
+            for time_budget in &mut self.time_budgets {
+                if time_budget.time_budget_type != TimeBudgetType::Day
+                    && time_budget.calendar_start_index == calendar_start_index
+                {
+                    time_budget.scheduled += slots;
+                }
+            }
         }
     }
 
@@ -45,15 +107,18 @@ impl Budget {
                     budget_cut_off_number = time_budget.min_scheduled;
                 }
                 ActivityType::GetToMinWeekBudget => {
-                    if time_budget.calendar_end_index - time_budget.calendar_start_index > 24 {
-                        //Week time_budget
-                        budget_cut_off_number = time_budget.min_scheduled; // this allows leaving room for other goals to get to min before topping up
-                    } else {
-                        //Day time_budget
+                    if time_budget.time_budget_type == TimeBudgetType::Day {
+                        budget_cut_off_number = time_budget.max_scheduled;
+                    } else if time_budget.scheduled >= time_budget.min_scheduled
+                        && self.is_below_min_of_period_containing(hour_index + offset)
+                    {
+                        //another week, month or custom period still needs to get to its min
                         budget_cut_off_number = time_budget.max_scheduled;
+                    } else {
+                        budget_cut_off_number = time_budget.min_scheduled; // this allows leaving room for other goals to get to min before topping up
                     }
                 }
-                ActivityType::TopUpWeekBudget => {
+                ActivityType::TopUpWeekBudget | ActivityType::TopUpSimpleGoal => {
                     budget_cut_off_number = time_budget.max_scheduled;
                 }
             }
@@ -73,14 +138,42 @@ impl Budget {
                 is_allowed = false;
             }
         }
+        if let Some(day_start_index) = self.get_day_start_index_of(hour_index) {
+            for days_budget in &self.days_budgets {
+                // the budget pass only plans the min days, the later passes can add days up to the max
+                let days_cut_off_number = match activity_type {
+                    ActivityType::Budget if days_budget.min_days > 0 => days_budget.min_days,
+                    _ => days_budget.max_days,
+                };
+                if days_budget.contains(hour_index)
+                    && !days_budget.days_scheduled.contains(&day_start_index)
+                    && days_budget.days_scheduled.len() >= days_cut_off_number
+                {
+                    is_allowed = false;
+                }
+            }
+        }
         is_allowed
     }
+
+    /// Whether a week, month or custom period time_budget containing hour_index hasn't reached its min yet
+    fn is_below_min_of_period_containing(&self, hour_index: usize) -> bool {
+        self.time_budgets.iter().any(|time_budget| {
+            time_budget.time_budget_type != TimeBudgetType::Day
+                && hour_index >= time_budget.calendar_start_index
+                && hour_index < time_budget.calendar_end_index
+                && time_budget.scheduled < time_budget.min_scheduled
+        })
+    }
 }
 
 #[derive(Deserialize, Debug, Clone, PartialEq)]
 pub enum TimeBudgetType {
     Day,
     Week,
+    Month,
+    /// Custom number of days, like a sprint
+    Period,
 }
 
 #[derive(Clone, Deserialize)]
@@ -110,53 +203,157 @@ impl Debug for TimeBudget {
     }
 }
 
+/// Number of distinct days with the goal in a full week
+#[derive(Clone, Deserialize, Debug)]
+pub struct DaysBudget {
+    pub calendar_start_index: usize,
+    pub calendar_end_index: usize,
+    /// Start index of each day with the goal
+    pub days_scheduled: Vec<usize>,
+    pub min_days: usize,
+    pub max_days: usize,
+}
+
+impl DaysBudget {
+    pub fn contains(&self, hour_index: usize) -> bool {
+        hour_index >= self.calendar_start_index && hour_index < self.calendar_end_index
+    }
+
+    pub fn get_days_missing(&self) -> usize {
+        self.min_days.saturating_sub(self.days_scheduled.len())
+    }
+}
+
+pub fn get_days_budgets_from(calendar: &Calendar, goal: &Goal) -> Vec<DaysBudget> {
+    let budget_config = goal.budget_config.as_ref().unwrap();
+    let mut days_budgets: Vec<DaysBudget> = vec![];
+    if budget_config.min_days_per_week.is_none() && budget_config.max_days_per_week.is_none() {
+        return days_budgets;
+    }
+    let mut week_start = calendar.start_date_time;
+    while week_start.add(Duration::days(7)) <= calendar.end_date_time {
+        let week_end = week_start.add(Duration::days(7));
+        days_budgets.push(DaysBudget {
+            calendar_start_index: calendar.get_index_of(week_start),
+            calendar_end_index: calendar.get_index_of(week_end),
+            days_scheduled: vec![],
+            min_days: budget_config.min_days_per_week.unwrap_or(0),
+            max_days: budget_config.max_days_per_week.unwrap_or(7),
+        });
+        week_start = week_end;
+    }
+    days_budgets
+}
+
 pub fn get_time_budgets_from(calendar: &Calendar, goal: &Goal) -> Vec<TimeBudget> {
     let mut time_budgets: Vec<TimeBudget> = vec![];
+    let filters = goal.filters.clone().unwrap_or_default();
+    let budget_config = goal.budget_config.as_ref().unwrap();
     //get a time_budget for each day
-    for hour_index in 24..calendar.hours.capacity() - 24 {
-        if (hour_index) % 24 == 0 {
-            println!("Day boundary detected at hour_index {:?}", &hour_index);
-            let mut min = goal.budget_config.as_ref().unwrap().min_per_day;
-            let mut max = goal.budget_config.as_ref().unwrap().max_per_day;
-            if goal.filters.as_ref().unwrap().on_days.contains(
-                &calendar
-                    .start_date_time
-                    .sub(Duration::hours(24))
-                    .add(Duration::hours(hour_index as i64))
-                    .weekday(),
-            ) {
-                //OK
-            } else {
-                min = 0;
-                max = 0;
-            }
-            time_budgets.push(TimeBudget {
-                time_budget_type: TimeBudgetType::Day,
-                calendar_start_index: hour_index,
-                calendar_end_index: hour_index + 24,
-                scheduled: 0,
-                min_scheduled: min,
-                max_scheduled: max,
-            });
+    let mut day_start = calendar.start_date_time;
+    while day_start < calendar.end_date_time {
+        let day_end = day_start.add(Duration::days(1));
+        println!("Day boundary detected at {:?}", &day_start);
+        let mut min =
+            calendar.get_slots_for_minutes(budget_config.get_min_per_day_on(day_start.weekday()));
+        let mut max =
+            calendar.get_slots_for_minutes(budget_config.get_max_per_day_on(day_start.weekday()));
+        if filters.on_days.contains(&day_start.weekday()) {
+            //OK
+        } else {
+            min = 0;
+            max = 0;
+        }
+        time_budgets.push(TimeBudget {
+            time_budget_type: TimeBudgetType::Day,
+            calendar_start_index: calendar.get_index_of(day_start),
+            calendar_end_index: calendar.get_index_of(day_end),
+            scheduled: 0,
+            min_scheduled: min,
+            max_scheduled: max,
+        });
+        day_start = day_end;
+    }
+
+    //get a time_budget for each full week
+    let mut week_start = calendar.start_date_time;
+    while week_start.add(Duration::days(7)) <= calendar.end_date_time {
+        let week_end = week_start.add(Duration::days(7));
+        println!("Week boundary detected at {:?}", &week_end);
+        time_budgets.push(TimeBudget {
+            time_budget_type: TimeBudgetType::Week,
+            calendar_start_index: calendar.get_index_of(week_start),
+            calendar_end_index: calendar.get_index_of(week_end),
+            scheduled: 0,
+            min_scheduled: calendar.get_slots_for_minutes(budget_config.min_per_week),
+            max_scheduled: calendar.get_slots_for_minutes(budget_config.max_per_week),
+        });
+        week_start = week_end;
+    }
+
+    //get a time_budget for each month, the last one can be partial
+    if budget_config.min_per_month.is_some() || budget_config.max_per_month.is_some() {
+        let mut month_start = calendar.start_date_time;
+        while month_start < calendar.end_date_time {
+            let month_end = month_start.checked_add_months(Months::new(1)).unwrap();
+            time_budgets.push(get_period_time_budget(
+                calendar,
+                TimeBudgetType::Month,
+                month_start,
+                month_end,
+                budget_config.min_per_month.unwrap_or(0),
+                budget_config.max_per_month,
+            ));
+            month_start = month_end;
         }
     }
 
-    let mut start_pointer = 24;
-    //get a time_budget for each week
-    for hour_index in 24..calendar.hours.capacity() {
-        if (hour_index - 24) % (24 * 7) == 0 && hour_index > 24 {
-            println!("Week boundary detected at hour_index {:?}", &hour_index);
-            time_budgets.push(TimeBudget {
-                time_budget_type: TimeBudgetType::Week,
-                calendar_start_index: start_pointer,
-                calendar_end_index: hour_index,
-                scheduled: 0,
-                min_scheduled: goal.budget_config.as_ref().unwrap().min_per_week,
-                max_scheduled: goal.budget_config.as_ref().unwrap().max_per_week,
-            });
-            start_pointer = hour_index
+    //get a time_budget for each custom period, the last one can be partial
+    if let Some(per_period) = budget_config.per_period.as_ref() {
+        let mut period_start = calendar.start_date_time;
+        while period_start < calendar.end_date_time {
+            let period_end = period_start.add(Duration::days(per_period.days as i64));
+            time_budgets.push(get_period_time_budget(
+                calendar,
+                TimeBudgetType::Period,
+                period_start,
+                period_end,
+                per_period.min,
+                Some(per_period.max),
+            ));
+            period_start = period_end;
         }
     }
     dbg!(&time_budgets);
     time_budgets
 }
+
+/// A period that runs past the end of the calendar only needs to get to the part of its min that fits in the calendar.
+/// Without a max the whole period can be used.
+fn get_period_time_budget(
+    calendar: &Calendar,
+    time_budget_type: TimeBudgetType,
+    period_start: NaiveDateTime,
+    period_end: NaiveDateTime,
+    min_minutes: usize,
+    max_minutes: Option<usize>,
+) -> TimeBudget {
+    let end = period_end.min(calendar.end_date_time);
+    let calendar_start_index = calendar.get_index_of(period_start);
+    let calendar_end_index = calendar.get_index_of(end);
+    let min_scheduled = calendar.get_slots_for_minutes(min_minutes)
+        * (end - period_start).num_minutes() as usize
+        / (period_end - period_start).num_minutes() as usize;
+    let max_scheduled = match max_minutes {
+        Some(max_minutes) => calendar.get_slots_for_minutes(max_minutes),
+        None => calendar_end_index - calendar_start_index,
+    };
+    TimeBudget {
+        time_budget_type,
+        calendar_start_index,
+        calendar_end_index,
+        scheduled: 0,
+        min_scheduled,
+        max_scheduled,
+    }
+}
@@ -1,8 +1,12 @@
-use super::budget::{get_time_budgets_from, Budget, TimeBudgetType};
-use super::goal::Goal;
-use super::task::{DayTasks, FinalTasks, Task};
-use chrono::{Datelike, Days, Duration, NaiveDateTime, Weekday};
+use super::activity::Activity;
+use super::budget::{get_days_budgets_from, get_time_budgets_from, Budget, TimeBudgetType};
+use super::goal::{check_for_dependency_cycles, deserialize_minutes, Goal};
+use super::slot::BusySlot;
+use super::task::{DayTasks, FinalTasks, PinnedTask, ScheduleChanges, Task, TaskDateTime};
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, Offset, TimeZone, Timelike, Weekday};
+use chrono_tz::Tz;
 use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
 use std::collections::HashMap;
 use std::fmt::{Debug, Formatter};
 use std::ops::{Add, Deref, Sub};
@@ -16,43 +20,332 @@ pub enum Hour {
         activity_title: String,
         activity_goalid: String,
     }, //TODO: add goal id and budget id to occupied registration so budget object is not necessary anymore!
+    /// Taken by a BusySlot before scheduling
+    Blocked {
+        title: String,
+    },
+    /// Kept free before or after a block of the goal
+    Buffer {
+        activity_title: String,
+        activity_goalid: String,
+    },
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct ImpossibleActivity {
     pub id: String,
-    pub hours_missing: usize,
+    pub hours_missing: f64,
     pub period_start_date_time: NaiveDateTime,
     pub period_end_date_time: NaiveDateTime,
+    /// Goal with a higher priority that has hours in the period
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub taken_by: Option<String>,
+    /// Only for budgets with days per week
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub days_missing: Option<usize>,
 }
 
+/// No more than max_consecutive minutes of occupied time without a free stretch of min_break minutes
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BreakRule {
+    #[serde(deserialize_with = "deserialize_minutes")]
+    pub max_consecutive: usize,
+    #[serde(deserialize_with = "deserialize_minutes")]
+    pub min_break: usize,
+}
+
+/// Optional goals come last, then higher priorities first, then goals that wait for others
+type RoundKey = (bool, Reverse<usize>, usize);
+
+pub const DEFAULT_SLOT_MINUTES: usize = 60;
+/// Goal id of the tasks for busy slots
+pub const BUSY_GOAL_ID: &str = "busy";
+/// Goal id of the tasks for buffers around goals
+pub const BUFFER_GOAL_ID: &str = "buffer";
+
 pub struct Calendar {
     pub start_date_time: NaiveDateTime,
     pub end_date_time: NaiveDateTime,
+    /// Length of each entry in hours, in minutes
+    pub slot_minutes: usize,
+    /// All date times in the calendar are local to this timezone - or naive if there is none
+    pub timezone: Option<Tz>,
+    /// One entry per slot, starting one day before start_date_time until one day after end_date_time.
+    /// Days with a daylight saving time switch have an hour more or less.
     pub hours: Vec<Rc<Hour>>,
+    first_slot_utc: NaiveDateTime,
     pub impossible_activities: Vec<ImpossibleActivity>,
+    /// Optional activities that didn't fit, these aren't impossible
+    pub skipped_activities: Vec<ImpossibleActivity>,
     pub budgets: Vec<Budget>,
+    /// Per slot the goal that had it in the previous schedule, if any
+    pub previous_goal_ids: Vec<Option<String>>,
+    previous_tasks: Option<Vec<Task>>,
+    break_rule: Option<BreakRule>,
+    /// Goals whose hours count as a break
+    break_goal_ids: Vec<String>,
+    /// Priority per goal id, including what the goal inherits from its parents
+    goal_priorities: HashMap<String, usize>,
+    /// Round of scheduling per goal id
+    goal_rounds: HashMap<String, usize>,
+    /// Rounds before this one are for goals that aren't optional
+    number_of_mandatory_rounds: usize,
 }
 
 impl Calendar {
-    pub fn new(start_date_time: NaiveDateTime, end_date_time: NaiveDateTime) -> Self {
+    pub fn new(
+        start_date_time: NaiveDateTime,
+        end_date_time: NaiveDateTime,
+        slot_minutes: usize,
+        timezone: Option<Tz>,
+    ) -> Self {
+        if slot_minutes == 0 || 60 % slot_minutes != 0 {
+            panic!("Slots of {:?} minutes don't fit in an hour", slot_minutes);
+        }
         let number_of_days = (end_date_time - start_date_time).num_days(); //Todo use this later to stop limiting compatible
         println!(
             "Calendar of {:?} days, from {:?} to {:?}",
             &number_of_days, &start_date_time, &end_date_time
         );
-        let mut hours = Vec::with_capacity(48 + number_of_days as usize * 24);
-        for _ in 0..hours.capacity() {
+        let first_slot_utc = get_utc_of(timezone, start_date_time.sub(Duration::days(1)));
+        let last_slot_end_utc = get_utc_of(
+            timezone,
+            start_date_time.add(Duration::days(number_of_days + 1)),
+        );
+        let number_of_slots =
+            (last_slot_end_utc - first_slot_utc).num_minutes() as usize / slot_minutes;
+        let mut hours = Vec::with_capacity(number_of_slots);
+        for _ in 0..number_of_slots {
             hours.push(Rc::new(Hour::Free));
         }
         Self {
             start_date_time,
             end_date_time,
+            slot_minutes,
+            timezone,
             hours,
+            first_slot_utc,
             impossible_activities: vec![],
+            skipped_activities: vec![],
             budgets: vec![],
+            previous_goal_ids: vec![None; number_of_slots],
+            previous_tasks: None,
+            break_rule: None,
+            break_goal_ids: vec![],
+            goal_priorities: HashMap::new(),
+            goal_rounds: HashMap::new(),
+            number_of_mandatory_rounds: 0,
+        }
+    }
+
+    /// Remembers where the goals were in the previous schedule, so activities can stay there and changes can be reported
+    pub fn add_previous_schedule(&mut self, previous: &FinalTasks) {
+        let previous_tasks: Vec<Task> = previous
+            .scheduled
+            .iter()
+            .flat_map(|day_tasks| day_tasks.tasks.iter().cloned())
+            .filter(is_goal_task)
+            // tasks outside of this calendar can't be compared with it
+            .filter(|task| {
+                task.start.naive_local() >= self.start_date_time
+                    && task.deadline.naive_local() <= self.end_date_time
+            })
+            .collect();
+        for task in &previous_tasks {
+            let start = task.start.naive_local();
+            let deadline = task.deadline.naive_local();
+            for index in self.get_index_of(start)..self.get_index_of(deadline) {
+                self.previous_goal_ids[index] = Some(task.goalid.clone());
+            }
+        }
+        self.previous_tasks = Some(previous_tasks);
+    }
+
+    pub fn add_priorities_from(&mut self, goals: &[Goal]) {
+        for goal in goals {
+            self.goal_priorities
+                .insert(goal.id.clone(), goal.get_inherited_priority(goals));
+        }
+    }
+
+    pub fn get_priority_of(&self, goal_id: &str) -> usize {
+        self.goal_priorities.get(goal_id).copied().unwrap_or(0)
+    }
+
+    /// Goals are scheduled in rounds, one per priority from the highest down,
+    /// and optional goals only get the time left after the other goals.
+    /// Needs the priorities to be added first.
+    pub fn add_rounds_from(&mut self, goals: &[Goal]) {
+        check_for_dependency_cycles(goals);
+        let mut round_keys: HashMap<String, RoundKey> = HashMap::new();
+        for goal in goals {
+            self.get_round_key_of(goal, goals, &mut round_keys);
+        }
+        let mut sorted_round_keys: Vec<RoundKey> = round_keys.values().copied().collect();
+        sorted_round_keys.sort();
+        sorted_round_keys.dedup();
+        self.number_of_mandatory_rounds = sorted_round_keys
+            .iter()
+            .filter(|(optional, _, _)| !optional)
+            .count();
+        self.goal_rounds = round_keys
+            .into_iter()
+            .map(|(goal_id, round_key)| {
+                (
+                    goal_id,
+                    sorted_round_keys.binary_search(&round_key).unwrap(),
+                )
+            })
+            .collect();
+    }
+
+    /// Goals wait for the round of the goals they depend on,
+    /// or the round after it if those still get time after the simple goals of that round
+    fn get_round_key_of(
+        &self,
+        goal: &Goal,
+        goals: &[Goal],
+        round_keys: &mut HashMap<String, RoundKey>,
+    ) -> RoundKey {
+        if let Some(round_key) = round_keys.get(&goal.id) {
+            return *round_key;
+        }
+        let mut round_key = (goal.optional, Reverse(self.get_priority_of(&goal.id)), 0);
+        for dependency in goals.iter().filter(|dependency| {
+            goal.depends_on
+                .as_ref()
+                .is_some_and(|goal_ids| goal_ids.contains(&dependency.id))
+        }) {
+            let (optional, priority, step) = self.get_round_key_of(dependency, goals, round_keys);
+            if dependency.budget_config.is_none() && dependency.max_duration.is_none() {
+                round_key = round_key.max((optional, priority, step));
+            } else {
+                round_key = round_key.max((optional, priority, step + 1));
+            }
+        }
+        round_keys.insert(goal.id.clone(), round_key);
+        round_key
+    }
+
+    /// The rounds of the goals that aren't optional, then the rounds of the optional goals.
+    /// Within a group the minimums of all rounds get placed before any round gets topped up.
+    pub fn get_round_groups(&self) -> Vec<Vec<usize>> {
+        let number_of_rounds = self.goal_rounds.values().max().map_or(0, |round| round + 1);
+        vec![
+            (0..self.number_of_mandatory_rounds).collect(),
+            (self.number_of_mandatory_rounds..number_of_rounds).collect(),
+        ]
+    }
+
+    pub fn is_in_round(&self, goal_id: &str, round: usize) -> bool {
+        self.goal_rounds.get(goal_id) == Some(&round)
+    }
+
+    /// The goal with the highest priority above the given one that occupies hours between start_index and end_index
+    pub fn get_goal_with_priority_above(
+        &self,
+        priority: usize,
+        start_index: usize,
+        end_index: usize,
+    ) -> Option<String> {
+        let mut goal_with_highest_priority: Option<(&String, usize)> = None;
+        for index in start_index..end_index.min(self.hours.len()) {
+            if let Hour::Occupied {
+                activity_goalid, ..
+            } = &*self.hours[index]
+            {
+                let goal_priority = self.get_priority_of(activity_goalid);
+                let highest_priority = goal_with_highest_priority.map_or(priority, |(_, p)| p);
+                if goal_priority > highest_priority {
+                    goal_with_highest_priority = Some((activity_goalid, goal_priority));
+                }
+            }
         }
+        goal_with_highest_priority.map(|(goal_id, _)| goal_id.clone())
+    }
+
+    pub fn add_break_rule(&mut self, break_rule: &BreakRule, goals: &[Goal]) {
+        if break_rule.max_consecutive == 0 || break_rule.min_break == 0 {
+            panic!("Invalid break rule {:?}", break_rule);
+        }
+        self.break_rule = Some(break_rule.clone());
+        self.break_goal_ids = goals
+            .iter()
+            .filter(|goal| goal.is_break)
+            .map(|goal| goal.id.clone())
+            .collect();
+    }
+
+    /// Longest block the goal can have without a break, if there is a break rule for it
+    pub fn get_max_consecutive_slots_for(&self, goal_id: &str) -> Option<usize> {
+        let break_rule = self.break_rule.as_ref()?;
+        if self.break_goal_ids.iter().any(|id| id == goal_id) {
+            return None;
+        }
+        Some(self.get_slots_for_minutes(break_rule.max_consecutive))
+    }
+
+    /// Checks if a block of the goal keeps every stretch of occupied hours within the break rule.
+    /// Occupied hours separated by less than a break count as one stretch.
+    pub fn is_within_break_rule(&self, goal_id: &str, index: usize, size: usize) -> bool {
+        let Some(max_consecutive_slots) = self.get_max_consecutive_slots_for(goal_id) else {
+            return true;
+        };
+        let min_break_slots =
+            self.get_slots_for_minutes(self.break_rule.as_ref().unwrap().min_break);
+        let occupied_before =
+            self.get_occupied_slots_until_break((0..index).rev(), min_break_slots);
+        let occupied_after =
+            self.get_occupied_slots_until_break(index + size..self.hours.len(), min_break_slots);
+        occupied_before + size + occupied_after <= max_consecutive_slots
+    }
+
+    fn get_occupied_slots_until_break(
+        &self,
+        indexes: impl Iterator<Item = usize>,
+        min_break_slots: usize,
+    ) -> usize {
+        let mut occupied_slots = 0;
+        let mut free_slots = 0;
+        for index in indexes {
+            let is_occupied = match &*self.hours[index] {
+                Hour::Occupied {
+                    activity_goalid, ..
+                } => !self.break_goal_ids.contains(activity_goalid),
+                Hour::Blocked { .. } => true,
+                Hour::Free | Hour::Buffer { .. } => false,
+            };
+            if is_occupied {
+                occupied_slots += 1;
+                free_slots = 0;
+            } else {
+                free_slots += 1;
+                if free_slots >= min_break_slots {
+                    break;
+                }
+            }
+        }
+        occupied_slots
+    }
+
+    pub fn get_slots_for_hours(&self, hours: usize) -> usize {
+        hours * 60 / self.slot_minutes
+    }
+
+    /// Rounds up to whole slots
+    pub fn get_slots_for_minutes(&self, minutes: usize) -> usize {
+        minutes.div_ceil(self.slot_minutes)
+    }
+
+    pub fn get_hours_for_slots(&self, slots: usize) -> f64 {
+        (slots * self.slot_minutes) as f64 / 60.0
+    }
+
+    pub fn get_minute_of_day(&self, index: usize) -> usize {
+        let time = self.get_date_time_of(index).time();
+        (time.hour() * 60 + time.minute()) as usize
     }
 
     pub fn get_week_day_of(&self, index_to_test: usize) -> Weekday {
@@ -63,14 +356,46 @@ impl Calendar {
                 self.hours.capacity()
             );
         }
-        let date_time_of_index_to_test = self
-            .start_date_time
-            .sub(Days::new(1))
-            .add(Duration::hours(index_to_test as i64));
-        date_time_of_index_to_test.weekday()
+        self.get_date_time_of(index_to_test).weekday()
+    }
+
+    /// Local date time at the start of the slot
+    pub fn get_date_time_of(&self, index: usize) -> NaiveDateTime {
+        let utc = self.get_utc_of_index(index);
+        match self.timezone {
+            Some(timezone) => timezone.from_utc_datetime(&utc).naive_local(),
+            None => utc,
+        }
+    }
+
+    fn get_utc_of_index(&self, index: usize) -> NaiveDateTime {
+        self.first_slot_utc
+            .add(Duration::minutes((index * self.slot_minutes) as i64))
+    }
+
+    /// Date time for output, with the UTC offset if the calendar has a timezone
+    pub fn get_task_date_time_of(&self, index: usize) -> TaskDateTime {
+        match self.timezone {
+            Some(timezone) => TaskDateTime::WithOffset(
+                timezone
+                    .from_utc_datetime(&self.get_utc_of_index(index))
+                    .fixed_offset(),
+            ),
+            None => TaskDateTime::Local(self.get_date_time_of(index)),
+        }
     }
 
     pub fn get_index_of(&self, date_time: NaiveDateTime) -> usize {
+        self.get_minutes_since_first_slot(date_time) / self.slot_minutes
+    }
+
+    /// Index right after the last slot that the period up to date_time touches
+    pub fn get_end_index_of(&self, date_time: NaiveDateTime) -> usize {
+        self.get_minutes_since_first_slot(date_time)
+            .div_ceil(self.slot_minutes)
+    }
+
+    fn get_minutes_since_first_slot(&self, date_time: NaiveDateTime) -> usize {
         if date_time < self.start_date_time.sub(Duration::days(1))
             || date_time > self.end_date_time.add(Duration::days(1))
         {
@@ -79,8 +404,30 @@ impl Calendar {
                 "can't request an index more than 1 day outside of calendar bounds for date {:?}\nCalendar starts at {:?} and ends at {:?}", date_time, self.start_date_time, self.end_date_time
             )
         }
-        (date_time - self.start_date_time.checked_sub_days(Days::new(1)).unwrap()).num_hours()
-            as usize
+        (get_utc_of(self.timezone, date_time) - self.first_slot_utc).num_minutes() as usize
+    }
+
+    /// Returns the index of the first hour occupied by the goal, if there is any.
+    pub fn get_start_index_of_goal(&self, goal_id: &str) -> Option<usize> {
+        self.hours.iter().position(|hour| match &**hour {
+            Hour::Occupied {
+                activity_goalid, ..
+            } => activity_goalid == goal_id,
+            Hour::Free | Hour::Blocked { .. } | Hour::Buffer { .. } => false,
+        })
+    }
+
+    /// Returns the index right after the last hour occupied by the goal before index_limit, if there is any.
+    pub fn get_end_index_of_goal_before(&self, goal_id: &str, index_limit: usize) -> Option<usize> {
+        (0..index_limit.min(self.hours.len()))
+            .rev()
+            .find(|index| match &*self.hours[*index] {
+                Hour::Occupied {
+                    activity_goalid, ..
+                } => activity_goalid == goal_id,
+                Hour::Free | Hour::Blocked { .. } | Hour::Buffer { .. } => false,
+            })
+            .map(|index| index + 1)
     }
 
     pub fn print(&self) -> FinalTasks {
@@ -95,19 +442,27 @@ impl Calendar {
             taskid: task_counter,
             goalid: "free".to_string(),
             title: "free".to_string(),
-            duration: 0,
-            start: self.start_date_time,
-            deadline: self.start_date_time, //just for init; will be overwritten
+            duration: 0.0,
+            start: TaskDateTime::Local(self.start_date_time),
+            deadline: TaskDateTime::Local(self.start_date_time), //just for init; will be overwritten
         };
-        for hour_offset in 24..(self.hours.capacity() - 24) {
-            if hour_offset % 24 == 0 && hour_offset != 24 {
+        let mut current_task_slots: usize = 0;
+        let first_index = self.get_index_of(self.start_date_time);
+        let mut current_task_start_index = first_index;
+        for hour_offset in first_index..self.get_index_of(self.end_date_time) {
+            if hour_offset != first_index
+                && self.get_date_time_of(hour_offset).date()
+                    != self.get_date_time_of(hour_offset - 1).date()
+            {
                 // day boundary reached
                 println!("found day boundary at offset :{:?}", hour_offset);
                 // - push current to dayTasks and increase counter
-                current_task.deadline = current_task
-                    .start
-                    .add(Duration::hours(current_task.duration as i64));
-                if current_task.duration > 0 {
+                self.set_start_duration_and_deadline(
+                    &mut current_task,
+                    current_task_start_index,
+                    current_task_slots,
+                );
+                if current_task_slots > 0 {
                     day_tasks.tasks.push(current_task.clone());
                 }
                 task_counter += 1;
@@ -116,34 +471,31 @@ impl Calendar {
                 scheduled.push(day_tasks);
                 // - update dayTasks for current day and reset Tasks vec
                 day_tasks = DayTasks {
-                    day: self
-                        .start_date_time
-                        .date()
-                        .add(Duration::days(hour_offset as i64 / 24 - 1)),
+                    day: self.get_date_time_of(hour_offset).date(),
                     tasks: Vec::with_capacity(1),
                 };
                 // - reset current_task and empty title to force new Task in loop
                 current_task.title = "".to_string();
-                current_task.duration = 0;
+                current_task_slots = 0;
             }
             match self.hours[hour_offset].clone().deref() {
                 Hour::Free => {
                     if current_task.title.eq(&"free".to_string()) {
-                        current_task.duration += 1;
+                        current_task_slots += 1;
                     } else {
-                        current_task.deadline = current_task
-                            .start
-                            .add(Duration::hours(current_task.duration as i64));
-                        if current_task.duration > 0 {
+                        self.set_start_duration_and_deadline(
+                            &mut current_task,
+                            current_task_start_index,
+                            current_task_slots,
+                        );
+                        if current_task_slots > 0 {
                             day_tasks.tasks.push(current_task.clone());
                             task_counter += 1;
                         }
                         current_task.title = "free".to_string();
                         current_task.goalid = "free".to_string();
-                        current_task.duration = 1;
-                        current_task.start = self
-                            .start_date_time
-                            .add(Duration::hours(hour_offset as i64 - 24)); // TODO: Fix magic number offset everywhere in code
+                        current_task_slots = 1;
+                        current_task_start_index = hour_offset;
                         current_task.taskid = task_counter;
                     }
                 }
@@ -155,39 +507,277 @@ impl Calendar {
                     if current_task.title.eq(&"free".to_string())
                         || current_task.title.ne(activity_title)
                     {
-                        if current_task.duration > 0 {
-                            current_task.deadline = current_task
-                                .start
-                                .add(Duration::hours(current_task.duration as i64));
+                        if current_task_slots > 0 {
+                            self.set_start_duration_and_deadline(
+                                &mut current_task,
+                                current_task_start_index,
+                                current_task_slots,
+                            );
                             // TODO is this necessary?
                             day_tasks.tasks.push(current_task.clone());
                             task_counter += 1;
                         }
-                        current_task.duration = 1;
+                        current_task_slots = 1;
                         current_task.goalid = activity_goalid.clone();
                         current_task.title = activity_title.clone();
-                        current_task.start = self
-                            .start_date_time
-                            .add(Duration::hours(hour_offset as i64 - 24)); // TODO: Fix magic number offset everywhere in code
+                        current_task_start_index = hour_offset;
+                        current_task.taskid = task_counter;
+                    } else {
+                        current_task_slots += 1;
+                    }
+                }
+                Hour::Blocked { title } => {
+                    if current_task.goalid.ne(BUSY_GOAL_ID) || current_task.title.ne(title) {
+                        if current_task_slots > 0 {
+                            self.set_start_duration_and_deadline(
+                                &mut current_task,
+                                current_task_start_index,
+                                current_task_slots,
+                            );
+                            day_tasks.tasks.push(current_task.clone());
+                            task_counter += 1;
+                        }
+                        current_task_slots = 1;
+                        current_task.goalid = BUSY_GOAL_ID.to_string();
+                        current_task.title = title.clone();
+                        current_task_start_index = hour_offset;
+                        current_task.taskid = task_counter;
+                    } else {
+                        current_task_slots += 1;
+                    }
+                }
+                Hour::Buffer { activity_title, .. } => {
+                    let buffer_title = format!("buffer for {}", activity_title);
+                    if current_task.goalid.ne(BUFFER_GOAL_ID)
+                        || current_task.title.ne(&buffer_title)
+                    {
+                        if current_task_slots > 0 {
+                            self.set_start_duration_and_deadline(
+                                &mut current_task,
+                                current_task_start_index,
+                                current_task_slots,
+                            );
+                            day_tasks.tasks.push(current_task.clone());
+                            task_counter += 1;
+                        }
+                        current_task_slots = 1;
+                        current_task.goalid = BUFFER_GOAL_ID.to_string();
+                        current_task.title = buffer_title;
+                        current_task_start_index = hour_offset;
                         current_task.taskid = task_counter;
                     } else {
-                        current_task.duration += 1;
+                        current_task_slots += 1;
                     }
                 }
             }
         }
-        current_task.deadline = current_task
-            .start
-            .add(Duration::hours(current_task.duration as i64));
-        if current_task.duration > 0 {
+        self.set_start_duration_and_deadline(
+            &mut current_task,
+            current_task_start_index,
+            current_task_slots,
+        );
+        if current_task_slots > 0 {
             // TODO is this necessary?
             day_tasks.tasks.push(current_task);
         }
         scheduled.push(day_tasks);
+        let changes = self.previous_tasks.as_ref().map(|previous_tasks| {
+            let tasks: Vec<Task> = scheduled
+                .iter()
+                .flat_map(|day_tasks| day_tasks.tasks.iter().cloned())
+                .filter(is_goal_task)
+                .collect();
+            ScheduleChanges::between(previous_tasks, &tasks)
+        });
         FinalTasks {
             scheduled,
             impossible: self.impossible_activities.clone(),
+            changes,
+            skipped: self.skipped_activities.clone(),
+        }
+    }
+
+    fn set_start_duration_and_deadline(&self, task: &mut Task, start_index: usize, slots: usize) {
+        task.start = self.get_task_date_time_of(start_index);
+        task.duration = self.get_hours_for_slots(slots);
+        task.deadline = self.get_task_date_time_of(start_index + slots);
+    }
+
+    /// Blocks the hours of the busy slots, so no activity can be placed there.
+    /// Busy slots are clipped to the calendar, and block every slot they touch.
+    pub fn add_busy_slots(&mut self, busy_slots: &[BusySlot]) {
+        let first_date_time = self.start_date_time.sub(Duration::days(1));
+        let last_date_time = self.end_date_time.add(Duration::days(1));
+        for busy_slot in busy_slots {
+            let start = busy_slot.start.max(first_date_time);
+            let end = busy_slot.end.min(last_date_time);
+            if start >= end {
+                continue;
+            }
+            let start_index = self.get_index_of(start);
+            let end_index = self.get_end_index_of(end).min(self.hours.len());
+            for index in start_index..end_index {
+                self.hours[index] = Rc::new(Hour::Blocked {
+                    title: busy_slot.title.clone(),
+                });
+            }
+        }
+    }
+
+    /// Buffers can take free hours, other buffers or hours of the goal itself
+    pub fn can_hold_buffer_of(&self, goal_id: &str, index: usize) -> bool {
+        match &*self.hours[index] {
+            Hour::Free | Hour::Buffer { .. } => true,
+            Hour::Occupied {
+                activity_goalid, ..
+            } => activity_goalid == goal_id,
+            Hour::Blocked { .. } => false,
+        }
+    }
+
+    /// Turns the free hours right before and after a placed block into buffers of its goal.
+    /// Returns the indexes of the new buffers.
+    pub fn add_buffers_around(
+        &mut self,
+        activity: &Activity,
+        block_index: usize,
+        block_size: usize,
+    ) -> Vec<usize> {
+        let before = block_index.saturating_sub(activity.buffer_before)..block_index;
+        let after = block_index + block_size
+            ..(block_index + block_size + activity.buffer_after).min(self.hours.len());
+        let mut buffer_indexes = vec![];
+        for index in before.chain(after) {
+            if *self.hours[index] == Hour::Free {
+                self.hours[index] = Rc::new(Hour::Buffer {
+                    activity_title: activity.title.clone(),
+                    activity_goalid: activity.goal_id.clone(),
+                });
+                buffer_indexes.push(index);
+            }
+        }
+        buffer_indexes
+    }
+
+    /// Occupies every slot a pinned task touches and counts them for the budgets.
+    /// Hours of a pinned task that are already taken or past the end of the calendar are logged as impossible.
+    /// Pinned tasks completely outside of the calendar or for unknown goals are ignored.
+    pub fn add_pinned_tasks(&mut self, pinned_tasks: &[PinnedTask], goals: &[Goal]) {
+        for (pinned_task_index, pinned_task) in pinned_tasks.iter().enumerate() {
+            let Some(goal) = goals.iter().find(|goal| goal.id == pinned_task.goal_id) else {
+                continue;
+            };
+            let pinned_task_end = pinned_task
+                .start
+                .add(Duration::minutes(pinned_task.duration as i64));
+            if pinned_task_end <= self.start_date_time || pinned_task.start >= self.end_date_time {
+                continue;
+            }
+            let mut conflicting_slots = 0;
+            if pinned_task_end > self.end_date_time {
+                conflicting_slots = self.get_slots_for_minutes(
+                    (pinned_task_end - self.end_date_time).num_minutes() as usize,
+                );
+            }
+            // the part before the start of the calendar is already done
+            let start_index = self.get_index_of(pinned_task.start.max(self.start_date_time));
+            let end_index = self.get_end_index_of(pinned_task_end.min(self.end_date_time));
+            for index in start_index..end_index {
+                if *self.hours[index] != Hour::Free {
+                    conflicting_slots += 1;
+                    continue;
+                }
+                self.hours[index] = Rc::new(Hour::Occupied {
+                    activity_index: pinned_task_index,
+                    activity_title: goal.title.clone(),
+                    activity_goalid: goal.id.clone(),
+                });
+                self.update_budgets_for(&goal.id, index);
+            }
+            if conflicting_slots > 0 {
+                self.impossible_activities.push(ImpossibleActivity {
+                    id: goal.id.clone(),
+                    hours_missing: self.get_hours_for_slots(conflicting_slots),
+                    period_start_date_time: pinned_task.start,
+                    period_end_date_time: pinned_task_end,
+                    taken_by: None,
+                    days_missing: None,
+                });
+            }
+        }
+    }
+
+    /// Checks that a block of the goal at the index either touches the other blocks of the goal,
+    /// or is at least min_gap_slots away from them
+    pub fn keeps_min_gap_of(&self, goal_id: &str, index: usize, min_gap_slots: usize) -> bool {
+        if min_gap_slots == 0 {
+            return true;
         }
+        let is_of_goal = |index: usize| match &*self.hours[index] {
+            Hour::Occupied {
+                activity_goalid, ..
+            } => activity_goalid == goal_id,
+            Hour::Free | Hour::Blocked { .. } | Hour::Buffer { .. } => false,
+        };
+        let gap_before = (index.saturating_sub(min_gap_slots + 1)..index)
+            .rev()
+            .position(is_of_goal);
+        let gap_after =
+            (index + 1..(index + min_gap_slots + 2).min(self.hours.len())).position(is_of_goal);
+        [gap_before, gap_after].iter().all(|gap| match gap {
+            Some(gap) => *gap == 0 || *gap >= min_gap_slots,
+            None => true,
+        })
+    }
+
+    /// Length of the block that size slots of the goal at the index make,
+    /// together with the hours of the goal right before and after them
+    pub fn get_block_size_of(&self, goal_id: &str, index: usize, size: usize) -> usize {
+        let is_of_goal = |index: &usize| match &*self.hours[*index] {
+            Hour::Occupied {
+                activity_goalid, ..
+            } => activity_goalid == goal_id,
+            Hour::Free | Hour::Blocked { .. } | Hour::Buffer { .. } => false,
+        };
+        let slots_before = (0..index).rev().take_while(is_of_goal).count();
+        let slots_after = (index + size..self.hours.len())
+            .take_while(is_of_goal)
+            .count();
+        slots_before + size + slots_after
+    }
+
+    /// Days with hours occupied by the goal
+    pub fn get_days_of_goal(&self, goal_id: &str) -> Vec<NaiveDate> {
+        let mut days: Vec<NaiveDate> = vec![];
+        for index in 0..self.hours.len() {
+            if let Hour::Occupied {
+                activity_goalid, ..
+            } = &*self.hours[index]
+            {
+                let day = self.get_date_time_of(index).date();
+                if activity_goalid == goal_id && !days.contains(&day) {
+                    days.push(day);
+                }
+            }
+        }
+        days
+    }
+
+    /// Number of hours between start_index and end_index that are occupied by the goal
+    pub fn get_slots_of_goal_between(
+        &self,
+        goal_id: &str,
+        start_index: usize,
+        end_index: usize,
+    ) -> usize {
+        (start_index..end_index.min(self.hours.len()))
+            .filter(|index| match &*self.hours[*index] {
+                Hour::Occupied {
+                    activity_goalid, ..
+                } => activity_goalid == goal_id,
+                Hour::Free | Hour::Blocked { .. } | Hour::Buffer { .. } => false,
+            })
+            .count()
     }
 
     pub fn add_budgets_from(&mut self, goals: &Vec<Goal>) {
@@ -201,20 +791,73 @@ impl Calendar {
                     //Check if budget_config is realistic
 
                     //check 1
-                    let mut min_per_day_sum = 0;
-                    for _ in goal.filters.clone().unwrap().on_days {
-                        min_per_day_sum += budget_config.min_per_day;
+                    let mut mins_per_day: Vec<usize> = goal
+                        .filters
+                        .clone()
+                        .unwrap_or_default()
+                        .on_days
+                        .iter()
+                        .map(|weekday| budget_config.get_min_per_day_on(*weekday))
+                        .collect();
+                    if let Some(min_days_per_week) = budget_config.min_days_per_week {
+                        // only the days with the goal need their min_per_day
+                        mins_per_day.sort();
+                        mins_per_day.truncate(min_days_per_week);
                     }
+                    let min_per_day_sum: usize = mins_per_day.iter().sum();
                     if min_per_day_sum > budget_config.min_per_week {
                         panic!("Sum of min_per_day {:?} is higher than min_per_week {:?} for goal {:?}", min_per_day_sum,budget_config.min_per_week, goal.title);
                     }
 
                     //check 2
-                    if budget_config.max_per_day > budget_config.max_per_week {
-                        panic!(
-                            "max_per_day {:?} is higher than max_per_week {:?} for goal {:?}",
-                            budget_config.max_per_day, budget_config.max_per_week, goal.title
-                        );
+                    for max_per_day in budget_config
+                        .per_weekday
+                        .values()
+                        .map(|day_budget_config| day_budget_config.max_per_day)
+                        .chain([budget_config.max_per_day])
+                    {
+                        if max_per_day > budget_config.max_per_week {
+                            panic!(
+                                "max_per_day {:?} is higher than max_per_week {:?} for goal {:?}",
+                                max_per_day, budget_config.max_per_week, goal.title
+                            );
+                        }
+                    }
+
+                    //check 3
+                    if let (Some(min_per_month), Some(max_per_month)) =
+                        (budget_config.min_per_month, budget_config.max_per_month)
+                    {
+                        if min_per_month > max_per_month {
+                            panic!(
+                                "min_per_month {:?} is higher than max_per_month {:?} for goal {:?}",
+                                min_per_month, max_per_month, goal.title
+                            );
+                        }
+                    }
+
+                    //check 4
+                    if let Some(per_period) = budget_config.per_period.as_ref() {
+                        if per_period.days == 0 || per_period.min > per_period.max {
+                            panic!(
+                                "Invalid period budget {:?} for goal {:?}",
+                                per_period, goal.title
+                            );
+                        }
+                    }
+                    //check 5
+                    if let Some(min_days_per_week) = budget_config.min_days_per_week {
+                        if min_days_per_week > budget_config.max_days_per_week.unwrap_or(7)
+                            || (min_days_per_week > 0 && budget_config.min_per_day == 0)
+                        {
+                            panic!(
+                                "Invalid days per week {:?}-{:?} with min_per_day {:?} for goal {:?}",
+                                budget_config.min_days_per_week,
+                                budget_config.max_days_per_week,
+                                budget_config.min_per_day,
+                                goal.title
+                            );
+                        }
                     }
                     budget_ids.push(goal.id.clone());
                 }
@@ -236,10 +879,15 @@ impl Calendar {
                     self.budgets.push(Budget {
                         originating_goal_id: budget_id.clone(),
                         participating_goals: descendants_added,
+                        optional: goal_map.get(&budget_id).unwrap().optional,
                         time_budgets: get_time_budgets_from(
                             self,
                             goal_map.get(&budget_id).as_ref().unwrap(),
                         ),
+                        days_budgets: get_days_budgets_from(
+                            self,
+                            goal_map.get(&budget_id).as_ref().unwrap(),
+                        ),
                     });
                     continue;
                 }
@@ -251,10 +899,15 @@ impl Calendar {
                     self.budgets.push(Budget {
                         originating_goal_id: budget_id.clone(),
                         participating_goals: descendants_added,
+                        optional: goal_map.get(&budget_id).unwrap().optional,
                         time_budgets: get_time_budgets_from(
                             self,
                             goal_map.get(&budget_id).as_ref().unwrap(),
                         ),
+                        days_budgets: get_days_budgets_from(
+                            self,
+                            goal_map.get(&budget_id).as_ref().unwrap(),
+                        ),
                     });
                     break;
                 }
@@ -271,6 +924,16 @@ impl Calendar {
                 descendants_added.push(descendant_of_which_to_add_children);
             }
         }
+
+        let calendar_start_index = self.get_index_of(self.start_date_time);
+        for goal in goals {
+            if let Some(completed) = goal.completed {
+                let slots = self.get_slots_for_minutes(completed);
+                for budget in &mut self.budgets {
+                    budget.add_completed_for(&goal.id, slots, calendar_start_index);
+                }
+            }
+        }
     }
 
     pub fn update_budgets_for(&mut self, goal: &str, duration_offset: usize) {
@@ -280,68 +943,156 @@ impl Calendar {
         }
     }
 
-    pub fn log_impossible_min_day_budgets(&mut self) {
+    pub fn log_impossible_children_durations(&mut self, goals: &[Goal]) {
         let mut impossible_activities = vec![];
-        for budget in &self.budgets {
-            for time_budget in &budget.time_budgets {
-                if time_budget.time_budget_type == TimeBudgetType::Day {
-                    // Good
-                } else {
-                    continue;
-                }
-                if time_budget.scheduled < time_budget.min_scheduled {
+        for goal in goals {
+            if goal.children.is_none() || goal.budget_config.is_some() {
+                continue;
+            }
+            let Some(parent_duration) = goal.min_duration else {
+                continue;
+            };
+            let children_duration = goal.get_children_duration(goals);
+            if children_duration > parent_duration {
+                let (adjusted_goal_start, adjusted_goal_deadline) =
+                    goal.get_adj_start_deadline(self);
+                impossible_activities.push(ImpossibleActivity {
+                    id: goal.id.clone(),
+                    hours_missing: (children_duration - parent_duration) as f64 / 60.0,
+                    period_start_date_time: adjusted_goal_start,
+                    period_end_date_time: adjusted_goal_deadline,
+                    taken_by: None,
+                    days_missing: None,
+                });
+            }
+        }
+        self.impossible_activities.extend(impossible_activities);
+    }
+
+    pub fn log_impossible_min_day_budgets(&mut self, round: usize) {
+        self.log_impossible_min_budgets(&[TimeBudgetType::Day], round);
+    }
+
+    /// Also logs the month and custom period budgets, as these get to their min in the same pass as the weeks
+    pub fn log_impossible_min_week_budgets(&mut self, round: usize) {
+        self.log_impossible_min_budgets(
+            &[
+                TimeBudgetType::Week,
+                TimeBudgetType::Month,
+                TimeBudgetType::Period,
+            ],
+            round,
+        );
+        self.log_impossible_min_days(round);
+    }
+
+    fn log_impossible_min_days(&mut self, round: usize) {
+        let mut impossible_activities = vec![];
+        for budget in self
+            .budgets
+            .iter()
+            .filter(|budget| self.is_in_round(&budget.originating_goal_id, round))
+        {
+            for days_budget in &budget.days_budgets {
+                if days_budget.get_days_missing() > 0 {
                     impossible_activities.push(ImpossibleActivity {
                         id: budget.originating_goal_id.clone(),
-                        hours_missing: time_budget.min_scheduled - time_budget.scheduled,
+                        hours_missing: self
+                            .get_hours_for_slots(budget.get_min_slots_of_missing_days(days_budget)),
                         period_start_date_time: self
-                            .start_date_time
-                            .add(Duration::hours(time_budget.calendar_start_index as i64)),
-                        period_end_date_time: self
-                            .start_date_time
-                            .add(Duration::hours(time_budget.calendar_end_index as i64)),
+                            .get_date_time_of(days_budget.calendar_start_index),
+                        period_end_date_time: self.get_date_time_of(days_budget.calendar_end_index),
+                        taken_by: self.get_goal_with_priority_above(
+                            self.get_priority_of(&budget.originating_goal_id),
+                            days_budget.calendar_start_index,
+                            days_budget.calendar_end_index,
+                        ),
+                        days_missing: Some(days_budget.get_days_missing()),
                     });
                 }
             }
         }
-        self.impossible_activities.extend(impossible_activities);
+        self.log_impossible_budgets(impossible_activities);
     }
 
-    pub fn log_impossible_min_week_budgets(&mut self) {
-        //TODO: merge with log_imossible_min_day_budgets, passing budget type as param
+    fn log_impossible_min_budgets(&mut self, time_budget_types: &[TimeBudgetType], round: usize) {
         let mut impossible_activities = vec![];
-        for budget in &self.budgets {
+        for budget in self
+            .budgets
+            .iter()
+            .filter(|budget| self.is_in_round(&budget.originating_goal_id, round))
+        {
             for time_budget in &budget.time_budgets {
-                if time_budget.time_budget_type == TimeBudgetType::Week {
+                if time_budget_types.contains(&time_budget.time_budget_type) {
                     // Good
                 } else {
                     continue;
                 }
-                if time_budget.scheduled < time_budget.min_scheduled {
+                if time_budget.scheduled < time_budget.min_scheduled
+                    && !budget.is_day_off(time_budget)
+                {
                     impossible_activities.push(ImpossibleActivity {
                         id: budget.originating_goal_id.clone(),
-                        hours_missing: time_budget.min_scheduled - time_budget.scheduled,
+                        hours_missing: self
+                            .get_hours_for_slots(time_budget.min_scheduled - time_budget.scheduled),
                         period_start_date_time: self
-                            .start_date_time
-                            .add(Duration::hours(time_budget.calendar_start_index as i64)),
-                        period_end_date_time: self
-                            .start_date_time
-                            .add(Duration::hours(time_budget.calendar_end_index as i64)),
+                            .get_date_time_of(time_budget.calendar_start_index),
+                        period_end_date_time: self.get_date_time_of(time_budget.calendar_end_index),
+                        taken_by: self.get_goal_with_priority_above(
+                            self.get_priority_of(&budget.originating_goal_id),
+                            time_budget.calendar_start_index,
+                            time_budget.calendar_end_index,
+                        ),
+                        days_missing: None,
                     });
                 }
             }
         }
-        self.impossible_activities.extend(impossible_activities);
+        self.log_impossible_budgets(impossible_activities);
     }
+
+    /// Optional budgets that don't get to their min are skipped, not impossible
+    fn log_impossible_budgets(&mut self, impossible_activities: Vec<ImpossibleActivity>) {
+        for impossible_activity in impossible_activities {
+            let is_optional = self.budgets.iter().any(|budget| {
+                budget.optional && budget.originating_goal_id == impossible_activity.id
+            });
+            if is_optional {
+                self.skipped_activities.push(impossible_activity);
+            } else {
+                self.impossible_activities.push(impossible_activity);
+            }
+        }
+    }
+}
+/// Free time, busy slots and buffers aren't tasks of a goal
+fn is_goal_task(task: &Task) -> bool {
+    task.goalid != "free" && task.goalid != BUSY_GOAL_ID && task.goalid != BUFFER_GOAL_ID
 }
+
+/// Local date times in a daylight saving time gap are moved forward by the length of the gap
+fn get_utc_of(timezone: Option<Tz>, local_date_time: NaiveDateTime) -> NaiveDateTime {
+    let Some(timezone) = timezone else {
+        return local_date_time;
+    };
+    match timezone.from_local_datetime(&local_date_time).earliest() {
+        Some(date_time) => date_time.naive_utc(),
+        None => {
+            let offset_before_gap = timezone
+                .offset_from_utc_datetime(&local_date_time.sub(Duration::days(1)))
+                .fix();
+            local_date_time.sub(Duration::seconds(offset_before_gap.local_minus_utc() as i64))
+        }
+    }
+}
+
 impl Debug for Calendar {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
         writeln!(f).unwrap();
         for index in 0..self.hours.capacity() {
             write!(f, "{:?} ", self.get_week_day_of(index)).unwrap();
-            let mut index_string = index.to_string();
-            if index > 23 {
-                index_string = index.to_string() + " " + &(index % 24).to_string();
-            }
+            let index_string =
+                index.to_string() + " " + &self.get_date_time_of(index).time().to_string();
             if self.hours[index] == Rc::new(Hour::Free) {
                 if Rc::weak_count(&self.hours[index]) == 0 {
                     writeln!(f, "{} -", index_string).unwrap();
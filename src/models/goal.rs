@@ -1,9 +1,12 @@
-use std::ops::{Add, Sub};
+use std::{
+    collections::HashMap,
+    ops::{Add, Sub},
+};
 
 use chrono::{Datelike, Duration, NaiveDateTime, Weekday};
-use serde::Deserialize;
+use serde::{de, Deserialize, Deserializer};
 
-use super::calendar::Calendar;
+use super::{calendar::Calendar, repetition::Repetition, slot::Slot};
 
 #[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
@@ -16,34 +19,323 @@ pub struct Goal {
     #[serde(rename = "budget")]
     pub budget_config: Option<BudgetConfig>,
     pub filters: Option<Filters>,
+    /// In minutes
+    #[serde(default, deserialize_with = "deserialize_optional_minutes")]
     pub min_duration: Option<usize>,
+    /// In minutes - the hours above min_duration are topped up when there is time left
+    #[serde(default, deserialize_with = "deserialize_optional_minutes")]
+    pub max_duration: Option<usize>,
     pub title: String,
     pub children: Option<Vec<String>>,
+    pub repeat: Option<Repetition>,
+    /// Goals that need to be finished before this Goal can start
+    pub depends_on: Option<Vec<String>>,
+    /// Shortest block to schedule at once, in minutes
+    #[serde(default, deserialize_with = "deserialize_optional_minutes")]
+    pub min_block_size: Option<usize>,
+    /// Longest block to schedule at once, in minutes
+    #[serde(default, deserialize_with = "deserialize_optional_minutes")]
+    pub max_block_size: Option<usize>,
+    /// Time already spent on this Goal, in minutes
+    #[serde(default, deserialize_with = "deserialize_optional_minutes")]
+    pub completed: Option<usize>,
+    /// Time to keep free before each block of this Goal, in minutes
+    #[serde(default, deserialize_with = "deserialize_optional_minutes")]
+    pub buffer_before: Option<usize>,
+    /// Time to keep free after each block of this Goal, in minutes
+    #[serde(default, deserialize_with = "deserialize_optional_minutes")]
+    pub buffer_after: Option<usize>,
+    /// Least time between two blocks of this Goal on the same day, in minutes - blocks can still be merged into one
+    #[serde(default, deserialize_with = "deserialize_optional_minutes")]
+    pub min_gap: Option<usize>,
+    /// Least number of days without this Goal between two days with it, like 1 for 'not on consecutive days'
+    pub min_days_between: Option<usize>,
+    /// Hours of this Goal count as a break, like sleep or lunch
+    #[serde(default)]
+    pub is_break: bool,
+    /// Times of day this Goal is rather scheduled in, other times stay possible
+    #[serde(default)]
+    pub preferred_windows: Vec<PreferredWindow>,
+    /// Goals with a higher priority get their time first, Goals without one take it from their parents or get 0
+    pub priority: Option<usize>,
+    /// Only scheduled in the time left after all other Goals, and skipped instead of impossible if it doesn't fit
+    #[serde(default)]
+    pub optional: bool,
 }
 
 #[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Filters {
+    /// In minutes after midnight
+    #[serde(default, deserialize_with = "deserialize_minutes")]
     pub after_time: usize,
+    /// In minutes after midnight
+    #[serde(default = "end_of_day", deserialize_with = "deserialize_minutes")]
     pub before_time: usize,
+    /// Several windows on the same day, like "7-9 or 18-21" - when given these replace after_time and before_time
+    #[serde(default)]
+    pub windows: Vec<TimeWindow>,
+    /// Windows for specific days, like "9-13" on Fridays - these replace the windows of the other days
+    #[serde(default)]
+    pub windows_on: HashMap<Weekday, Vec<TimeWindow>>,
+    #[serde(default = "every_day")]
     pub on_days: Vec<Weekday>,
+    /// Periods that can't be used, like vacations, appointments or postponed hours
+    #[serde(default)]
+    pub not_on: Vec<Slot>,
+}
+
+/// Without filters a Goal can use any hour, every day
+impl Default for Filters {
+    fn default() -> Self {
+        Self {
+            after_time: 0,
+            before_time: end_of_day(),
+            windows: vec![],
+            windows_on: HashMap::new(),
+            on_days: every_day(),
+            not_on: vec![],
+        }
+    }
+}
+
+impl Filters {
+    pub fn get_windows(&self) -> Vec<TimeWindow> {
+        if self.windows.is_empty() {
+            vec![TimeWindow {
+                after_time: self.after_time,
+                before_time: self.before_time,
+            }]
+        } else {
+            self.windows.clone()
+        }
+    }
+
+    pub fn get_windows_on(&self, weekday: Weekday) -> Vec<TimeWindow> {
+        match self.windows_on.get(&weekday) {
+            Some(windows) => windows.clone(),
+            None => self.get_windows(),
+        }
+    }
+
+    /// A window crossing midnight belongs to the day it starts on,
+    /// so the hours after midnight are checked against the windows of the day before
+    pub fn windows_contain(&self, weekday: Weekday, minute_of_day: usize) -> bool {
+        self.get_windows_on(weekday).iter().any(|window| {
+            window.contains(minute_of_day)
+                && (!window.crosses_midnight() || minute_of_day >= window.after_time)
+        }) || self
+            .get_windows_on(weekday.pred())
+            .iter()
+            .any(|window| window.crosses_midnight() && minute_of_day < window.before_time)
+    }
+
+    /// Windows of all days together
+    pub fn get_all_windows(&self) -> Vec<TimeWindow> {
+        let mut windows = self.get_windows();
+        for windows_on_day in self.windows_on.values() {
+            windows.extend(windows_on_day.iter().cloned());
+        }
+        windows
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TimeWindow {
+    /// In minutes after midnight
+    #[serde(deserialize_with = "deserialize_minutes")]
+    pub after_time: usize,
+    /// In minutes after midnight
+    #[serde(deserialize_with = "deserialize_minutes")]
+    pub before_time: usize,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PreferredWindow {
+    #[serde(flatten)]
+    pub window: TimeWindow,
+    /// How strongly the window is preferred, every slot in it outweighs this many claims of other activities
+    #[serde(default = "default_preference_weight")]
+    pub weight: usize,
+}
+
+fn default_preference_weight() -> usize {
+    1
+}
+
+impl TimeWindow {
+    /// If after time is not before the before time, for example 'Sleep 22-6', the window crosses midnight
+    pub fn crosses_midnight(&self) -> bool {
+        self.after_time >= self.before_time
+    }
+
+    pub fn contains(&self, minute_of_day: usize) -> bool {
+        if self.crosses_midnight() {
+            minute_of_day >= self.after_time || minute_of_day < self.before_time
+        } else {
+            minute_of_day >= self.after_time && minute_of_day < self.before_time
+        }
+    }
 }
 
 #[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct BudgetConfig {
+    #[serde(deserialize_with = "deserialize_minutes")]
     pub min_per_day: usize,
+    #[serde(deserialize_with = "deserialize_minutes")]
     pub max_per_day: usize,
+    #[serde(deserialize_with = "deserialize_minutes")]
     pub min_per_week: usize,
+    #[serde(deserialize_with = "deserialize_minutes")]
     pub max_per_week: usize,
+    #[serde(default, deserialize_with = "deserialize_optional_minutes")]
+    pub min_per_month: Option<usize>,
+    #[serde(default, deserialize_with = "deserialize_optional_minutes")]
+    pub max_per_month: Option<usize>,
+    /// Budget for a custom number of days, like a sprint of 2 weeks
+    pub per_period: Option<PeriodBudgetConfig>,
+    /// Amounts for specific days, like 1 hour on Saturdays - these replace min_per_day and max_per_day
+    #[serde(default)]
+    pub per_weekday: HashMap<Weekday, DayBudgetConfig>,
+    /// Number of days with this Goal in a week, like 'run on 3 days per week' - min_per_day then only applies on those days
+    pub min_days_per_week: Option<usize>,
+    pub max_days_per_week: Option<usize>,
+}
+
+impl BudgetConfig {
+    pub fn get_min_per_day_on(&self, weekday: Weekday) -> usize {
+        match self.per_weekday.get(&weekday) {
+            Some(day_budget_config) => day_budget_config.min_per_day,
+            None => self.min_per_day,
+        }
+    }
+
+    pub fn get_max_per_day_on(&self, weekday: Weekday) -> usize {
+        match self.per_weekday.get(&weekday) {
+            Some(day_budget_config) => day_budget_config.max_per_day,
+            None => self.max_per_day,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DayBudgetConfig {
+    #[serde(deserialize_with = "deserialize_minutes")]
+    pub min_per_day: usize,
+    #[serde(deserialize_with = "deserialize_minutes")]
+    pub max_per_day: usize,
+}
+
+/// Min and max time for every period of `days` days, counted from the start of the calendar
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PeriodBudgetConfig {
+    pub days: usize,
+    #[serde(deserialize_with = "deserialize_minutes")]
+    pub min: usize,
+    #[serde(deserialize_with = "deserialize_minutes")]
+    pub max: usize,
 }
 
 impl Goal {
-    pub fn get_adj_start_deadline(&self, calendar: &Calendar) -> (NaiveDateTime, NaiveDateTime) {
-        let mut adjusted_goal_start = self.start;
+    /// Sum of the min_duration of the direct children of this Goal, in minutes
+    pub fn get_children_duration(&self, goals: &[Goal]) -> usize {
+        let Some(children) = self.children.as_ref() else {
+            return 0;
+        };
+        goals
+            .iter()
+            .filter(|goal| children.contains(&goal.id))
+            .map(|goal| goal.min_duration.unwrap_or(0))
+            .sum()
+    }
+
+    /// Priority of this Goal, or of the closest parent that has one
+    pub fn get_inherited_priority(&self, goals: &[Goal]) -> usize {
+        let mut goal = self;
+        // a parent chain can't be longer than the number of goals
+        for _ in 0..goals.len() {
+            if let Some(priority) = goal.priority {
+                return priority;
+            }
+            let Some(parent) = goals.iter().find(|parent| {
+                parent
+                    .children
+                    .as_ref()
+                    .is_some_and(|children| children.contains(&goal.id))
+            }) else {
+                break;
+            };
+            goal = parent;
+        }
+        goal.priority.unwrap_or(0)
+    }
+
+    /// A parent Goal with a min_duration gets a filler Goal for the hours not consumed by its children.
+    /// The filler Goal has the same id and title as the parent, so its hours are shown as the parent.
+    pub fn get_filler_goal(&self, goals: &[Goal]) -> Option<Goal> {
+        if self.children.is_none() || self.budget_config.is_some() {
+            return None;
+        }
+        let parent_duration = self.min_duration?;
+        let children_duration = self.get_children_duration(goals);
+        if children_duration >= parent_duration {
+            return None;
+        }
+        let mut filler_goal = self.clone();
+        filler_goal.min_duration = Some(parent_duration - children_duration);
+        filler_goal.children = None;
+        Some(filler_goal)
+    }
+
+    /// Min and max block size in calendar slots, the defaults are used when the Goal doesn't set them
+    pub fn get_block_sizes(
+        &self,
+        calendar: &Calendar,
+        default_min_block_size: usize,
+        default_max_block_size: usize,
+    ) -> (usize, usize) {
+        let max_block_size = match self.max_block_size {
+            Some(minutes) => calendar.get_slots_for_minutes(minutes),
+            None => default_max_block_size,
+        };
+        let min_block_size = match self.min_block_size {
+            Some(minutes) => calendar.get_slots_for_minutes(minutes),
+            // the default never asks for more than the max block size allows
+            None => default_min_block_size.min(max_block_size),
+        };
+        if min_block_size == 0 || min_block_size > max_block_size {
+            panic!(
+                "Invalid block sizes {:?}-{:?} for goal {:?}",
+                self.min_block_size, self.max_block_size, self.title
+            );
+        }
+        (min_block_size, max_block_size)
+    }
+
+    /// Buffers before and after each block in calendar slots
+    pub fn get_buffers(&self, calendar: &Calendar) -> (usize, usize) {
+        (
+            calendar.get_slots_for_minutes(self.buffer_before.unwrap_or(0)),
+            calendar.get_slots_for_minutes(self.buffer_after.unwrap_or(0)),
+        )
+    }
+
+    /// Start of the Goal, or of the calendar if the Goal has none
+    pub fn get_start(&self, calendar: &Calendar) -> NaiveDateTime {
         if self.start.year() == 1970 {
-            adjusted_goal_start = calendar.start_date_time;
+            calendar.start_date_time
+        } else {
+            self.start
         }
+    }
+
+    pub fn get_adj_start_deadline(&self, calendar: &Calendar) -> (NaiveDateTime, NaiveDateTime) {
+        let mut adjusted_goal_start = self.get_start(calendar);
         let mut adjusted_goal_deadline = self.deadline;
         if self.deadline.year() == 1970 {
             adjusted_goal_deadline = calendar.end_date_time;
@@ -52,18 +344,18 @@ impl Goal {
             return (adjusted_goal_start, adjusted_goal_deadline);
         }
 
-        let filter_option = self.filters.clone().unwrap();
-        if filter_option.after_time < filter_option.clone().before_time {
-            //normal case
-        } else {
+        let windows = self.filters.clone().unwrap().get_all_windows();
+        if windows.iter().any(|window| window.crosses_midnight()) {
             // special case where we know that compatible times cross the midnight boundary
+            // the days then start at the earliest window, so a day holds all its windows
+            let earliest_after_time = windows.iter().map(|window| window.after_time).min();
             println!(
                 "Special case adjusting start from {:?}",
                 &adjusted_goal_start
             );
             adjusted_goal_start = adjusted_goal_start
                 .sub(Duration::hours(24))
-                .add(Duration::hours(filter_option.after_time as i64));
+                .add(Duration::minutes(earliest_after_time.unwrap() as i64));
             println!("... to {:?}", &adjusted_goal_start);
             adjusted_goal_deadline = adjusted_goal_start.add(Duration::days(
                 (adjusted_goal_deadline - adjusted_goal_start).num_days() + 1,
@@ -72,3 +364,103 @@ impl Goal {
         (adjusted_goal_start, adjusted_goal_deadline)
     }
 }
+
+/// Times and durations are given in hours (`8`, `0.5`) or as `"H:MM"` (`"8:30"`, `"0:15"`)
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum HoursOrHoursAndMinutes {
+    Hours(f64),
+    HoursAndMinutes(String),
+}
+
+impl HoursOrHoursAndMinutes {
+    fn to_minutes<E: de::Error>(&self) -> Result<usize, E> {
+        match self {
+            HoursOrHoursAndMinutes::Hours(hours) if *hours >= 0.0 => {
+                Ok((hours * 60.0).round() as usize)
+            }
+            HoursOrHoursAndMinutes::Hours(hours) => {
+                Err(E::custom(format!("negative number of hours {:?}", hours)))
+            }
+            HoursOrHoursAndMinutes::HoursAndMinutes(text) => text
+                .split_once(':')
+                .and_then(|(hours, minutes)| {
+                    let hours: usize = hours.trim().parse().ok()?;
+                    let minutes: usize = minutes.trim().parse().ok()?;
+                    (minutes < 60).then_some(hours * 60 + minutes)
+                })
+                .ok_or_else(|| E::custom(format!("expected \"H:MM\" but got {:?}", text))),
+        }
+    }
+}
+
+fn end_of_day() -> usize {
+    24 * 60
+}
+
+fn every_day() -> Vec<Weekday> {
+    vec![
+        Weekday::Mon,
+        Weekday::Tue,
+        Weekday::Wed,
+        Weekday::Thu,
+        Weekday::Fri,
+        Weekday::Sat,
+        Weekday::Sun,
+    ]
+}
+
+pub(crate) fn deserialize_minutes<'de, D>(deserializer: D) -> Result<usize, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    HoursOrHoursAndMinutes::deserialize(deserializer)?.to_minutes()
+}
+
+fn deserialize_optional_minutes<'de, D>(deserializer: D) -> Result<Option<usize>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<HoursOrHoursAndMinutes>::deserialize(deserializer)?
+        .map(|value| value.to_minutes())
+        .transpose()
+}
+
+/// Panics if goals depend on each other in a circle, as none of them could ever start.
+pub fn check_for_dependency_cycles(goals: &[Goal]) {
+    let goal_map: HashMap<&str, &Goal> = goals.iter().map(|g| (g.id.as_str(), g)).collect();
+    let mut finished: Vec<&str> = vec![];
+    for goal in goals {
+        let mut path: Vec<&str> = vec![];
+        visit_dependencies_of(goal.id.as_str(), &goal_map, &mut path, &mut finished);
+    }
+}
+
+fn visit_dependencies_of<'a>(
+    goal_id: &'a str,
+    goal_map: &HashMap<&'a str, &'a Goal>,
+    path: &mut Vec<&'a str>,
+    finished: &mut Vec<&'a str>,
+) {
+    if finished.contains(&goal_id) {
+        return;
+    }
+    if path.contains(&goal_id) {
+        panic!(
+            "Goal {:?} depends on itself via {:?}",
+            goal_id,
+            path.join(" -> ")
+        );
+    }
+    path.push(goal_id);
+    if let Some(dependencies) = goal_map
+        .get(goal_id)
+        .and_then(|goal| goal.depends_on.as_ref())
+    {
+        for dependency in dependencies {
+            visit_dependencies_of(dependency.as_str(), goal_map, path, finished);
+        }
+    }
+    path.pop();
+    finished.push(goal_id);
+}
@@ -2,4 +2,6 @@ pub mod activity;
 pub mod budget;
 pub mod calendar;
 pub mod goal;
+pub mod repetition;
+pub mod slot;
 pub mod task;
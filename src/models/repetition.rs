@@ -0,0 +1,97 @@
+use std::str::FromStr;
+
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, Weekday};
+use serde::{de, Deserialize, Deserializer};
+
+/// How often a Goal repeats between its start and deadline.
+/// Every repetition becomes a separate occurrence with its own period, which is turned into its own Activity.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Repetition {
+    Daily,
+    Weekly,
+    /// Every day from Monday till Friday - accepts "weekdays" or "mon-fri"
+    Weekdays,
+    /// Every Saturday and Sunday
+    Weekends,
+    EveryXdays(usize),
+    EveryXhours(usize),
+}
+
+impl Repetition {
+    /// Splits the period between start and deadline into one period per occurrence.
+    /// A last occurrence that doesn't fit completely before the deadline is cut off at the deadline.
+    /// The first occurrence is for first_day, even when it starts the evening before for windows across midnight.
+    pub fn get_occurrences(
+        &self,
+        start: NaiveDateTime,
+        deadline: NaiveDateTime,
+        first_day: NaiveDate,
+    ) -> Vec<(NaiveDateTime, NaiveDateTime)> {
+        let period = match self {
+            Repetition::Daily | Repetition::Weekdays | Repetition::Weekends => Duration::days(1),
+            Repetition::Weekly => Duration::days(7),
+            Repetition::EveryXdays(days) => Duration::days(*days as i64),
+            Repetition::EveryXhours(hours) => Duration::hours(*hours as i64),
+        };
+        let mut occurrences = vec![];
+        let mut occurrence_start = start;
+        while occurrence_start < deadline {
+            let occurrence_end = (occurrence_start + period).min(deadline);
+            let day_of_occurrence = first_day + (occurrence_start - start);
+            if self.is_allowed_on(day_of_occurrence.weekday()) {
+                occurrences.push((occurrence_start, occurrence_end));
+            }
+            occurrence_start += period;
+        }
+        occurrences
+    }
+
+    fn is_allowed_on(&self, weekday: Weekday) -> bool {
+        match self {
+            Repetition::Weekdays => !matches!(weekday, Weekday::Sat | Weekday::Sun),
+            Repetition::Weekends => matches!(weekday, Weekday::Sat | Weekday::Sun),
+            _ => true,
+        }
+    }
+}
+
+impl FromStr for Repetition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "daily" => Ok(Repetition::Daily),
+            "weekly" => Ok(Repetition::Weekly),
+            "weekdays" | "mon-fri" => Ok(Repetition::Weekdays),
+            "weekends" => Ok(Repetition::Weekends),
+            every_x => {
+                let parts: Vec<&str> = every_x.split_whitespace().collect();
+                if parts.len() != 3 || parts[0] != "every" {
+                    return Err(format!("unknown repetition {:?}", s));
+                }
+                let number: usize = parts[1]
+                    .parse()
+                    .map_err(|_| format!("invalid number in repetition {:?}", s))?;
+                if number == 0 {
+                    return Err(format!("repetition {:?} can't be every 0", s));
+                }
+                match parts[2] {
+                    "hours" | "hour" => Ok(Repetition::EveryXhours(number)),
+                    "days" | "day" => Ok(Repetition::EveryXdays(number)),
+                    "weeks" | "week" => Ok(Repetition::EveryXdays(number * 7)),
+                    _ => Err(format!("unknown unit in repetition {:?}", s)),
+                }
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Repetition {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
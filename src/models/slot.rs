@@ -0,0 +1,24 @@
+use chrono::NaiveDateTime;
+use serde::Deserialize;
+
+/// A period of time: [start; end[
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct Slot {
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+}
+
+impl Slot {
+    /// True if the Slot has any time in [start; end[
+    pub fn overlaps(&self, start: NaiveDateTime, end: NaiveDateTime) -> bool {
+        start < self.end && end > self.start
+    }
+}
+
+/// A Slot that is already taken outside of the scheduler, like a meeting from another calendar
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct BusySlot {
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+    pub title: String,
+}
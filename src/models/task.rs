@@ -1,13 +1,69 @@
 ///Tasks are only used for outputting
-use chrono::{NaiveDate, NaiveDateTime};
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime};
 use serde::{Deserialize, Serialize};
 
-use super::calendar::ImpossibleActivity;
+use super::{calendar::ImpossibleActivity, goal::deserialize_minutes};
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct FinalTasks {
     pub scheduled: Vec<DayTasks>,
     pub impossible: Vec<ImpossibleActivity>,
+    /// Only when the input has a previous schedule
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub changes: Option<ScheduleChanges>,
+    /// Optional goals that didn't fit
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skipped: Vec<ImpossibleActivity>,
+}
+
+/// Differences with the previous schedule
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct ScheduleChanges {
+    pub moved: Vec<MovedTask>,
+    pub added: Vec<Task>,
+    pub removed: Vec<Task>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct MovedTask {
+    pub from: Task,
+    pub to: Task,
+}
+
+impl ScheduleChanges {
+    /// Tasks of a goal with the same start and duration are unchanged.
+    /// Other Tasks of a goal are moved, as far as both schedules have them, and added or removed otherwise.
+    pub fn between(previous_tasks: &[Task], tasks: &[Task]) -> ScheduleChanges {
+        let is_unchanged = |task: &Task, other_tasks: &[Task]| {
+            other_tasks.iter().any(|other_task| {
+                other_task.goalid == task.goalid
+                    && other_task.start == task.start
+                    && other_task.duration == task.duration
+            })
+        };
+        let mut changes = ScheduleChanges::default();
+        let mut removed: Vec<&Task> = previous_tasks
+            .iter()
+            .filter(|previous_task| !is_unchanged(previous_task, tasks))
+            .collect();
+        for task in tasks {
+            if is_unchanged(task, previous_tasks) {
+                continue;
+            }
+            match removed
+                .iter()
+                .position(|previous_task| previous_task.goalid == task.goalid)
+            {
+                Some(position) => changes.moved.push(MovedTask {
+                    from: removed.remove(position).clone(),
+                    to: task.clone(),
+                }),
+                None => changes.added.push(task.clone()),
+            }
+        }
+        changes.removed = removed.into_iter().cloned().collect();
+        changes
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -16,9 +72,27 @@ pub struct Task {
     pub taskid: usize,
     pub goalid: String,
     pub title: String,
-    pub duration: usize,
-    pub start: NaiveDateTime,
-    pub deadline: NaiveDateTime,
+    /// In hours
+    pub duration: f64,
+    pub start: TaskDateTime,
+    pub deadline: TaskDateTime,
+}
+
+/// Local date time, including the UTC offset if the calendar has a timezone
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum TaskDateTime {
+    Local(NaiveDateTime),
+    WithOffset(DateTime<FixedOffset>),
+}
+
+impl TaskDateTime {
+    pub fn naive_local(&self) -> NaiveDateTime {
+        match self {
+            TaskDateTime::Local(date_time) => *date_time,
+            TaskDateTime::WithOffset(date_time) => date_time.naive_local(),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -26,3 +100,14 @@ pub struct DayTasks {
     pub day: NaiveDate,
     pub tasks: Vec<Task>,
 }
+
+/// A Task accepted from an earlier schedule, that has to stay where it is
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PinnedTask {
+    pub goal_id: String,
+    pub start: NaiveDateTime,
+    /// In minutes
+    #[serde(deserialize_with = "deserialize_minutes")]
+    pub duration: usize,
+}
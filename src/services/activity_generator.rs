@@ -1,20 +1,37 @@
 use crate::models::{activity::Activity, budget::TimeBudgetType, calendar::Calendar, goal::Goal};
 
-pub fn generate_simple_goal_activities(calendar: &Calendar, goals: &Vec<Goal>) -> Vec<Activity> {
+pub fn generate_simple_goal_activities(
+    calendar: &Calendar,
+    goals: &Vec<Goal>,
+    round: usize,
+) -> Vec<Activity> {
     dbg!(&goals);
     let mut activities: Vec<Activity> = Vec::with_capacity(goals.capacity());
-    for goal in goals {
-        let mut goal_activities = Activity::get_activities_from_simple_goal(goal, calendar);
+    for goal in goals
+        .iter()
+        .filter(|goal| calendar.is_in_round(&goal.id, round))
+    {
+        let mut goal_activities = match goal.get_filler_goal(goals) {
+            Some(filler_goal) => Activity::get_activities_from_simple_goal(&filler_goal, calendar),
+            None => Activity::get_activities_from_simple_goal(goal, calendar),
+        };
         dbg!(&goal_activities);
         activities.append(&mut goal_activities);
     }
     activities
 }
 
-pub fn generate_budget_goal_activities(calendar: &Calendar, goals: &Vec<Goal>) -> Vec<Activity> {
+pub fn generate_budget_goal_activities(
+    calendar: &Calendar,
+    goals: &Vec<Goal>,
+    round: usize,
+) -> Vec<Activity> {
     dbg!(&goals);
     let mut activities: Vec<Activity> = Vec::with_capacity(goals.capacity());
-    for goal in goals {
+    for goal in goals
+        .iter()
+        .filter(|goal| calendar.is_in_round(&goal.id, round))
+    {
         let mut goal_activities = Activity::get_activities_from_budget_goal(goal, calendar);
         dbg!(&goal_activities);
         activities.append(&mut goal_activities);
@@ -25,12 +42,17 @@ pub fn generate_budget_goal_activities(calendar: &Calendar, goals: &Vec<Goal>) -
 pub fn generate_get_to_week_min_budget_activities(
     calendar: &Calendar,
     goals: &[Goal],
+    round: usize,
 ) -> Vec<Activity> {
     let mut get_to_week_min_budget_activities = vec![];
-    for budget in &calendar.budgets {
+    for budget in calendar
+        .budgets
+        .iter()
+        .filter(|budget| calendar.is_in_round(&budget.originating_goal_id, round))
+    {
         let mut is_min_week_reached = true;
         for time_budget in &budget.time_budgets {
-            if time_budget.time_budget_type == TimeBudgetType::Week { //TODO: Assuming only one week time_budget per budget - need to make multi-week compatilble
+            if time_budget.time_budget_type != TimeBudgetType::Day { //TODO: Assuming only one week time_budget per budget - need to make multi-week compatilble
                  // Good
             } else {
                 continue;
@@ -70,9 +92,14 @@ pub fn generate_get_to_week_min_budget_activities(
 pub fn generate_top_up_week_budget_activities(
     calendar: &Calendar,
     goals: &[Goal],
+    round: usize,
 ) -> Vec<Activity> {
     let mut top_up_activities = vec![];
-    for budget in &calendar.budgets {
+    for budget in calendar
+        .budgets
+        .iter()
+        .filter(|budget| calendar.is_in_round(&budget.originating_goal_id, round))
+    {
         let goal_to_use: &Goal = goals
             .iter()
             .find(|g| g.id.eq(&budget.originating_goal_id))
@@ -90,6 +117,38 @@ pub fn generate_top_up_week_budget_activities(
             }
         }
     }
+    for activity in &mut top_up_activities {
+        activity.update_overlay_with_dependents(calendar, goals);
+    }
     dbg!(&top_up_activities);
     top_up_activities
 }
+
+/// Simple goals with a max duration get topped up after the budgets, unless their min duration was already impossible
+pub fn generate_top_up_simple_goal_activities(
+    calendar: &Calendar,
+    goals: &[Goal],
+    round: usize,
+) -> Vec<Activity> {
+    let mut top_up_activities = vec![];
+    for goal in goals
+        .iter()
+        .filter(|goal| calendar.is_in_round(&goal.id, round))
+    {
+        if calendar
+            .impossible_activities
+            .iter()
+            .chain(&calendar.skipped_activities)
+            .any(|impossible_activity| impossible_activity.id == goal.id)
+        {
+            continue;
+        }
+        top_up_activities.extend(Activity::get_activities_to_top_up_simple_goal(
+            goal, calendar,
+        ));
+    }
+    for activity in &mut top_up_activities {
+        activity.update_overlay_with_dependents(calendar, goals);
+    }
+    top_up_activities
+}
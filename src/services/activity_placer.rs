@@ -7,8 +7,21 @@ use crate::models::{
 
 pub fn place(calendar: &mut Calendar, mut activities: Vec<Activity>) {
     loop {
-        for activity in activities.iter_mut() {
-            activity.update_overlay_with(&calendar.budgets);
+        for index in 0..activities.len() {
+            let is_waiting = is_waiting_for_dependencies(&activities, index);
+            let activity = &mut activities[index];
+            let status_before_update = activity.status.clone();
+            if !is_waiting {
+                activity.update_overlay_with_dependencies(calendar);
+            }
+            activity.update_overlay_with(calendar);
+            if activity.activity_type == ActivityType::SimpleGoal
+                && status_before_update != Status::Impossible
+                && activity.status == Status::Impossible
+            {
+                activity.release_claims();
+                log_impossible(calendar, activity);
+            }
         }
         let act_index_to_schedule = find_act_index_to_schedule(&activities);
         if act_index_to_schedule.is_none() {
@@ -28,32 +41,30 @@ pub fn place(calendar: &mut Calendar, mut activities: Vec<Activity>) {
                 &activities[act_index_to_schedule.unwrap()].goal_id
             );
         }
-        let best_hour_index_and_size: Option<(usize, usize)> =
-            activities[act_index_to_schedule.unwrap()].get_best_scheduling_index_and_length();
+        let best_hour_index_and_size: Option<(usize, usize)> = activities
+            [act_index_to_schedule.unwrap()]
+        .get_best_scheduling_index_and_length(calendar);
         let best_hour_index: usize;
         let best_size: usize;
-        if best_hour_index_and_size.is_some() {
-            best_hour_index = best_hour_index_and_size.unwrap().0;
-            best_size = best_hour_index_and_size.unwrap().1;
+        if let Some((index, size)) = best_hour_index_and_size {
+            best_hour_index = index;
+            best_size = size;
             println!(
                 "Best index:{:?} and size {:?}",
                 &best_hour_index, &best_size
             );
         } else {
             activities[act_index_to_schedule.unwrap()].release_claims();
-            if activities[act_index_to_schedule.unwrap()].activity_type == ActivityType::Budget {
+            if activities[act_index_to_schedule.unwrap()].activity_type == ActivityType::Budget
+                || activities[act_index_to_schedule.unwrap()].activity_type
+                    == ActivityType::TopUpSimpleGoal
+            {
                 activities[act_index_to_schedule.unwrap()].status = Status::Processed;
                 continue;
             } else {
                 activities[act_index_to_schedule.unwrap()].status = Status::Impossible;
             }
-            let impossible_activity = ImpossibleActivity {
-                id: activities[act_index_to_schedule.unwrap()].goal_id.clone(),
-                hours_missing: activities[act_index_to_schedule.unwrap()].duration_left,
-                period_start_date_time: calendar.start_date_time,
-                period_end_date_time: calendar.end_date_time,
-            };
-            calendar.impossible_activities.push(impossible_activity);
+            log_impossible(calendar, &activities[act_index_to_schedule.unwrap()]);
             continue;
         }
         println!("reserving {:?} hours...", best_size);
@@ -71,9 +82,26 @@ pub fn place(calendar: &mut Calendar, mut activities: Vec<Activity>) {
             );
             activities[act_index_to_schedule.unwrap()].duration_left -= 1;
         }
+        let buffer_indexes = calendar.add_buffers_around(
+            &activities[act_index_to_schedule.unwrap()],
+            best_hour_index,
+            best_size,
+        );
+        let goal_id = activities[act_index_to_schedule.unwrap()].goal_id.clone();
+        for activity in activities
+            .iter_mut()
+            .filter(|activity| activity.goal_id == goal_id)
+        {
+            activity.claim_own_buffers(calendar, &buffer_indexes);
+        }
         if activities[act_index_to_schedule.unwrap()].duration_left == 0 {
             activities[act_index_to_schedule.unwrap()].status = Status::Scheduled;
             (activities[act_index_to_schedule.unwrap()]).release_claims();
+        } else if activities[act_index_to_schedule.unwrap()].activity_type
+            == ActivityType::SimpleGoal
+        {
+            activities[act_index_to_schedule.unwrap()]
+                .keep_sessions_apart(best_hour_index, best_size);
         }
 
         dbg!(&calendar);
@@ -81,12 +109,51 @@ pub fn place(calendar: &mut Calendar, mut activities: Vec<Activity>) {
     dbg!(&calendar);
 }
 
+fn log_impossible(calendar: &mut Calendar, activity: &Activity) {
+    let impossible_activity = ImpossibleActivity {
+        id: activity.goal_id.clone(),
+        hours_missing: calendar.get_hours_for_slots(activity.duration_left),
+        period_start_date_time: activity.start,
+        period_end_date_time: activity.deadline,
+        taken_by: calendar.get_goal_with_priority_above(
+            activity.priority,
+            calendar.get_index_of(activity.start),
+            calendar.get_index_of(activity.deadline),
+        ),
+        days_missing: None,
+    };
+    if activity.optional {
+        calendar.skipped_activities.push(impossible_activity);
+    } else {
+        calendar.impossible_activities.push(impossible_activity);
+    }
+}
+
+/// An activity has to wait while any goal it depends on still has activities to place
+fn is_waiting_for_dependencies(activities: &[Activity], index: usize) -> bool {
+    activities[index].depends_on.iter().any(|goal_id| {
+        activities
+            .iter()
+            .any(|activity| activity.goal_id.eq(goal_id) && activity.status == Status::Unprocessed)
+    })
+}
+
+fn is_ready_to_schedule(activities: &[Activity], index: usize) -> bool {
+    activities[index].status != Status::Scheduled
+        && activities[index].status != Status::Impossible
+        && activities[index].status != Status::Processed
+        && !is_waiting_for_dependencies(activities, index)
+}
+
+/// Activities of the goals with the highest priority go first, so lower priorities lose out when time runs out
 fn find_act_index_to_schedule(activities: &[Activity]) -> Option<usize> {
+    let highest_priority = (0..activities.len())
+        .filter(|index| is_ready_to_schedule(activities, *index))
+        .map(|index| activities[index].priority)
+        .max()?;
     let mut act_index_to_schedule = None;
     for index in 0..activities.len() {
-        if activities[index].status == Status::Scheduled
-            || activities[index].status == Status::Impossible
-            || activities[index].status == Status::Processed
+        if !is_ready_to_schedule(activities, index) || activities[index].priority < highest_priority
         {
             continue;
         }
@@ -1,6 +1,9 @@
+use crate::models::calendar::{BreakRule, DEFAULT_SLOT_MINUTES};
 use crate::models::goal::Goal;
-use crate::models::task::FinalTasks;
+use crate::models::slot::BusySlot;
+use crate::models::task::{FinalTasks, PinnedTask};
 use chrono::NaiveDateTime;
+use chrono_tz::Tz;
 use serde::Deserialize;
 use std::error::Error;
 use std::fs::File;
@@ -13,7 +16,27 @@ use std::path::Path;
 pub struct Input {
     pub start_date: NaiveDateTime,
     pub end_date: NaiveDateTime,
+    /// Calendar resolution in minutes, should fit a whole number of times in an hour
+    #[serde(default = "default_slot_minutes")]
+    pub slot_minutes: usize,
+    /// IANA timezone like "Europe/Amsterdam" - fixed offsets are available as "Etc/GMT-2".
+    /// All date times in the input are local to this timezone.
+    pub timezone: Option<Tz>,
     pub goals: Vec<Goal>,
+    /// Events from other calendars that the scheduler has to work around
+    #[serde(default)]
+    pub busy: Vec<BusySlot>,
+    /// Tasks from an earlier schedule that have to stay where they are
+    #[serde(default)]
+    pub pinned: Vec<PinnedTask>,
+    /// Output of an earlier run, activities then stay where they were as much as possible
+    pub previous: Option<FinalTasks>,
+    /// Mandatory breaks after a stretch of occupied time
+    pub breaks: Option<BreakRule>,
+}
+
+fn default_slot_minutes() -> usize {
+    DEFAULT_SLOT_MINUTES
 }
 
 pub fn get_input_from_json<P: AsRef<Path>>(path: P) -> Result<Input, Box<dyn Error>> {